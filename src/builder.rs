@@ -0,0 +1,435 @@
+use crate::*;
+
+/// A builder for `CompressionOptions`, which avoids spelling out
+/// `..CompressionOptions::default()` and takes globs as anything that converts
+/// into a `String`. Start one with `CompressionOptions::builder`.
+///
+/// Anything that isn't set keeps the value from `CompressionOptions::default`.
+///
+/// # Example
+///
+/// ```
+/// use perseus_compress::{CompressionLevel, CompressionOptions};
+///
+/// let options = CompressionOptions::builder()
+///     .include("./dist/static/**/*.css")
+///     .exclude("./dist/static/dont_compress.css")
+///     .level(CompressionLevel::Best)
+///     .build()?;
+/// # Ok::<(), perseus_compress::ConfigError>(())
+/// ```
+pub struct CompressionOptionsBuilder {
+    options: CompressionOptions<String>,
+    /// Whether the includes are still the defaults, which the first call to
+    /// `include` replaces
+    default_include: bool,
+}
+
+impl CompressionOptions<String> {
+    /// Starts building options with the builder API
+    pub fn builder() -> CompressionOptionsBuilder {
+        CompressionOptionsBuilder::default()
+    }
+}
+
+impl Default for CompressionOptionsBuilder {
+    fn default() -> Self {
+        Self {
+            options: CompressionOptions::converted_defaults(),
+            default_include: true,
+        }
+    }
+}
+
+impl CompressionOptionsBuilder {
+    /// Adds a glob for included files. The first call replaces the default
+    /// includes.
+    pub fn include(mut self, glob: impl Into<String>) -> Self {
+        if self.default_include {
+            self.options.include.clear();
+            self.default_include = false;
+        }
+        self.options.include.push(glob.into());
+        self
+    }
+
+    /// Adds a glob for excluded files, in addition to the default exclude for
+    /// source maps
+    pub fn exclude(mut self, glob: impl Into<String>) -> Self {
+        self.options.exclude.push(glob.into());
+        self
+    }
+
+    /// Replaces the excludes, including the default one for source maps
+    pub fn excludes(mut self, globs: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.options.exclude = globs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets `CompressionOptions::include_html`
+    pub fn include_html(mut self, include_html: bool) -> Self {
+        self.options.include_html = include_html;
+        self
+    }
+
+    /// Sets `CompressionOptions::include_svg`
+    pub fn include_svg(mut self, include_svg: bool) -> Self {
+        self.options.include_svg = include_svg;
+        self
+    }
+
+    /// Sets `CompressionOptions::include_fonts`
+    pub fn include_fonts(mut self, include_fonts: bool) -> Self {
+        self.options.include_fonts = include_fonts;
+        self
+    }
+
+    /// Adds a regular expression files matched by an include glob must also
+    /// match
+    #[cfg(feature = "regex")]
+    pub fn include_regex(mut self, regex: impl Into<String>) -> Self {
+        self.options.include_regex.push(regex.into());
+        self
+    }
+
+    /// Adds a regular expression for excluded files
+    #[cfg(feature = "regex")]
+    pub fn exclude_regex(mut self, regex: impl Into<String>) -> Self {
+        self.options.exclude_regex.push(regex.into());
+        self
+    }
+
+    /// Sets `CompressionOptions::base_dir`
+    pub fn base_dir(mut self, base_dir: impl Into<std::path::PathBuf>) -> Self {
+        self.options.base_dir = Some(base_dir.into());
+        self
+    }
+
+    /// Sets `CompressionOptions::fail_on_empty_glob`
+    pub fn fail_on_empty_glob(mut self, fail_on_empty_glob: bool) -> Self {
+        self.options.fail_on_empty_glob = fail_on_empty_glob;
+        self
+    }
+
+    /// Sets `CompressionOptions::follow_symlinks`
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.options.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// Sets `CompressionOptions::should_run`, either to a `bool` or to a
+    /// `ShouldRun::lazy` closure
+    pub fn should_run(mut self, should_run: impl Into<ShouldRun>) -> Self {
+        self.options.should_run = should_run.into();
+        self
+    }
+
+    /// Replaces the algorithms to compress with
+    pub fn algorithms(mut self, algorithms: impl IntoIterator<Item = Algorithm>) -> Self {
+        self.options.algorithms = algorithms.into_iter().collect();
+        self
+    }
+
+    /// Sets `CompressionOptions::threads`
+    #[cfg(feature = "parallel")]
+    pub fn threads(mut self, threads: Threads) -> Self {
+        self.options.threads = threads;
+        self
+    }
+
+    /// Sets `CompressionOptions::max_in_flight_bytes`
+    #[cfg(feature = "parallel")]
+    pub fn max_in_flight_bytes(mut self, max_in_flight_bytes: u64) -> Self {
+        self.options.max_in_flight_bytes = Some(max_in_flight_bytes);
+        self
+    }
+
+    /// Sets `CompressionOptions::level`
+    pub fn level(mut self, level: CompressionLevel) -> Self {
+        self.options.level = level;
+        self
+    }
+
+    /// Sets `CompressionOptions::buffer_size`
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.options.buffer_size = Some(buffer_size);
+        self
+    }
+
+    /// Sets `CompressionOptions::mmap_threshold`
+    #[cfg(feature = "mmap")]
+    pub fn mmap_threshold(mut self, threshold: u64) -> Self {
+        self.options.mmap_threshold = Some(threshold);
+        self
+    }
+
+    /// Sets `CompressionOptions::incremental`
+    pub fn incremental(mut self, incremental: bool) -> Self {
+        self.options.incremental = incremental;
+        self
+    }
+
+    /// Sets `CompressionOptions::force`
+    pub fn force(mut self, force: bool) -> Self {
+        self.options.force = force;
+        self
+    }
+
+    /// Sets `CompressionOptions::cache`
+    #[cfg(feature = "cache")]
+    pub fn cache(mut self, cache: impl Into<std::path::PathBuf>) -> Self {
+        self.options.cache = Some(cache.into());
+        self
+    }
+
+    /// Sets `CompressionOptions::preserve_mtime`
+    pub fn preserve_mtime(mut self, preserve_mtime: bool) -> Self {
+        self.options.preserve_mtime = preserve_mtime;
+        self
+    }
+
+    /// Sets `CompressionOptions::min_size`
+    pub fn min_size(mut self, min_size: u64) -> Self {
+        self.options.min_size = min_size;
+        self
+    }
+
+    /// Sets `CompressionOptions::max_size`
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.options.max_size = Some(max_size);
+        self
+    }
+
+    /// Adds an override for the files matched by `glob`. Overrides are
+    /// matched in the order they're added.
+    pub fn per_file(mut self, glob: impl Into<String>, file_options: PerFileOptions) -> Self {
+        self.options.overrides.push((glob.into(), file_options));
+        self
+    }
+
+    /// Compresses files with `extension` with `algorithms` instead, see
+    /// `CompressionOptions::algorithm_overrides`
+    pub fn algorithm_override(
+        mut self,
+        extension: impl Into<String>,
+        algorithms: impl IntoIterator<Item = Algorithm>,
+    ) -> Self {
+        let algorithms = algorithms.into_iter().collect();
+        self.options
+            .algorithm_overrides
+            .push((extension.into(), algorithms));
+        self
+    }
+
+    /// Compresses files larger than `threshold` bytes at `level`, see
+    /// `CompressionOptions::large_file_threshold`
+    pub fn large_files(mut self, threshold: u64, level: CompressionLevel) -> Self {
+        self.options.large_file_threshold = Some(threshold);
+        self.options.large_file_level = level;
+        self
+    }
+
+    /// Sets `CompressionOptions::skip_if_larger`
+    pub fn skip_if_larger(mut self, skip_if_larger: bool) -> Self {
+        self.options.skip_if_larger = skip_if_larger;
+        self
+    }
+
+    /// Sets `CompressionOptions::min_ratio`
+    pub fn min_ratio(mut self, min_ratio: f32) -> Self {
+        self.options.min_ratio = min_ratio;
+        self
+    }
+
+    /// Sets `CompressionOptions::skip_pre_compressed`
+    pub fn skip_pre_compressed(mut self, skip_pre_compressed: bool) -> Self {
+        self.options.skip_pre_compressed = skip_pre_compressed;
+        self
+    }
+
+    /// Adds an extension to skip in addition to the built-in pre-compressed
+    /// formats
+    pub fn skip_extension(mut self, extension: impl Into<String>) -> Self {
+        self.options.extra_skip_extensions.push(extension.into());
+        self
+    }
+
+    /// Sets `CompressionOptions::on_error`
+    pub fn on_error(mut self, on_error: ErrorPolicy) -> Self {
+        self.options.on_error = on_error;
+        self
+    }
+
+    /// Adds a custom encoder
+    pub fn encoder(mut self, encoder: impl Encoder + Send + Sync + 'static) -> Self {
+        self.options.encoders.push(Box::new(encoder));
+        self
+    }
+
+    /// Sets `CompressionOptions::custom_compressor`
+    pub fn custom_compressor(mut self, compressor: impl Compressor + 'static) -> Self {
+        self.options.custom_compressor = Some(Box::new(compressor));
+        self
+    }
+
+    /// Sets a callback for `CompressionOptions::on_progress`
+    pub fn on_progress(
+        mut self,
+        on_progress: impl Fn(&std::path::Path, usize, usize) + Send + Sync + 'static,
+    ) -> Self {
+        self.options.on_progress = Some(Box::new(on_progress));
+        self
+    }
+
+    /// Sets a callback for `CompressionOptions::before_compress`
+    pub fn before_compress(
+        mut self,
+        before_compress: impl Fn(&std::path::Path) + Send + Sync + 'static,
+    ) -> Self {
+        self.options.before_compress = Some(Box::new(before_compress));
+        self
+    }
+
+    /// Sets a callback for `CompressionOptions::after_compress`
+    pub fn after_compress(
+        mut self,
+        after_compress: impl Fn(&std::path::Path, CompressionFileResult) + Send + Sync + 'static,
+    ) -> Self {
+        self.options.after_compress = Some(Box::new(after_compress));
+        self
+    }
+
+    /// Sets `CompressionOptions::log_level`
+    #[cfg(feature = "log")]
+    pub fn log_level(mut self, log_level: log::Level) -> Self {
+        self.options.log_level = log_level;
+        self
+    }
+
+    /// Sets `CompressionOptions::write_report`
+    #[cfg(feature = "json")]
+    pub fn write_report(mut self, write_report: bool) -> Self {
+        self.options.write_report = write_report;
+        self
+    }
+
+    /// Sets `CompressionOptions::manifest`
+    #[cfg(feature = "json")]
+    pub fn manifest(mut self, manifest: impl Into<std::path::PathBuf>) -> Self {
+        self.options.manifest = Some(manifest.into());
+        self
+    }
+
+    /// Sets `CompressionOptions::generate_headers_file`
+    pub fn generate_headers_file(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.options.generate_headers_file = Some(path.into());
+        self
+    }
+
+    /// Sets `CompressionOptions::dry_run`
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.options.dry_run = dry_run;
+        self
+    }
+
+    /// Sets `CompressionOptions::verify`
+    #[cfg(feature = "verify")]
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.options.verify = verify;
+        self
+    }
+
+    /// Sets `CompressionOptions::prune_orphans`
+    pub fn prune_orphans(mut self, prune_orphans: bool) -> Self {
+        self.options.prune_orphans = prune_orphans;
+        self
+    }
+
+    /// Sets `CompressionOptions::output_dir`
+    pub fn output_dir(mut self, output_dir: impl Into<std::path::PathBuf>) -> Self {
+        self.options.output_dir = Some(output_dir.into());
+        self
+    }
+
+    /// Sets `CompressionOptions::output_root`
+    pub fn output_root(mut self, output_root: impl Into<std::path::PathBuf>) -> Self {
+        self.options.output_root = Some(output_root.into());
+        self
+    }
+
+    /// Sets `CompressionOptions::copy_sources`
+    pub fn copy_sources(mut self, copy_sources: bool) -> Self {
+        self.options.copy_sources = copy_sources;
+        self
+    }
+
+    /// Sets `CompressionOptions::naming`
+    pub fn naming(mut self, naming: NamingScheme) -> Self {
+        self.options.naming = naming;
+        self
+    }
+
+    /// Names the outputs of the encoder with `suffix` with `output_suffix`
+    /// instead, see `CompressionOptions::output_suffixes`
+    pub fn output_suffix(
+        mut self,
+        suffix: impl Into<String>,
+        output_suffix: impl Into<String>,
+    ) -> Self {
+        self.options
+            .output_suffixes
+            .push((suffix.into(), output_suffix.into()));
+        self
+    }
+
+    /// Sets `CompressionOptions::in_place`
+    pub fn in_place(mut self, in_place: bool) -> Self {
+        self.options.in_place = in_place;
+        self
+    }
+
+    /// Sets `CompressionOptions::brotli_dictionary`
+    #[cfg(feature = "brotli")]
+    pub fn brotli_dictionary(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.options.brotli_dictionary = Some(path.into());
+        self
+    }
+
+    /// Sets `CompressionOptions::zstd_train_dictionary`
+    #[cfg(feature = "zstd")]
+    pub fn zstd_train_dictionary(mut self, max_size: usize) -> Self {
+        self.options.zstd_train_dictionary = Some(max_size);
+        self
+    }
+
+    /// Sets `BrotliParams::quality` for every brotli algorithm
+    #[cfg(feature = "brotli")]
+    pub fn brotli_quality(mut self, quality: u32) -> Self {
+        for algorithm in &mut self.options.algorithms {
+            #[allow(irrefutable_let_patterns)]
+            if let Algorithm::Brotli(params) = algorithm {
+                params.quality = quality;
+            }
+        }
+        self
+    }
+
+    /// Sets `GzipParams::level` for every gzip algorithm
+    #[cfg(feature = "gzip")]
+    pub fn gzip_level(mut self, level: u32) -> Self {
+        for algorithm in &mut self.options.algorithms {
+            #[allow(irrefutable_let_patterns)]
+            if let Algorithm::Gzip(params) = algorithm {
+                params.level = level;
+            }
+        }
+        self
+    }
+
+    /// Finishes building the options, checking that every glob parses and,
+    /// on the server, that the algorithms' parameters and levels are in range
+    pub fn build(self) -> Result<CompressionOptions<String>, ConfigError> {
+        self.options.validate()?;
+        Ok(self.options)
+    }
+}
@@ -0,0 +1,278 @@
+//! The content-hash cache kept in `CompressionOptions::cache`, which skips
+//! sources that haven't changed since the last run regardless of their mtimes
+
+use crate::encoder::LoadedEncoder;
+use crate::{
+    hex, normalize_path, write_output, CompressionError, CompressionLevel, CompressionOptions,
+};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// What a source was compressed with in a run
+struct Entry {
+    /// SHA-256 of the source
+    hash: String,
+    /// The level, encoders and outputs the source was compressed with, see
+    /// `Cache::key`
+    settings: String,
+    /// The compressed files that were written, with their SHA-256
+    outputs: Vec<(PathBuf, String)>,
+    /// Compressed files an earlier run wrote that still exist, but weren't
+    /// written again, e.g. of an algorithm that was switched off since. They
+    /// still count as the plugin's own for `clean_compressed_files`.
+    stale: Vec<PathBuf>,
+}
+
+/// The hash and settings a source would be compressed with in this run
+pub(crate) struct CacheKey {
+    hash: String,
+    /// `None` if one of the encoders' dictionaries couldn't be read, in which
+    /// case the source is always compressed
+    settings: Option<String>,
+}
+
+pub(crate) struct Cache {
+    path: PathBuf,
+    /// Entries from the cache file
+    previous: HashMap<PathBuf, Entry>,
+    /// Whether there was a cache file from this version to read the entries
+    /// from
+    loaded: bool,
+    /// Entries for this run, which replace the file's once it's done
+    current: Mutex<HashMap<PathBuf, Entry>>,
+    /// SHA-256 of every dictionary the encoders compress against
+    dictionaries: HashMap<PathBuf, String>,
+}
+
+impl Cache {
+    /// Reads the cache file at `path`. A missing, corrupt or outdated cache
+    /// file just means every source is compressed.
+    pub(crate) fn load<M: AsRef<str> + Send>(
+        path: &Path,
+        encoders: &[LoadedEncoder],
+        options: &CompressionOptions<M>,
+    ) -> Self {
+        let previous = match std::fs::read(path) {
+            Ok(contents) => parse(&contents).or_else(|| {
+                warn!(
+                    options,
+                    "ignoring cache {}: it's corrupt or from another version",
+                    path.display()
+                );
+                None
+            }),
+            Err(err) => {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    warn!(options, "ignoring cache {}: {}", path.display(), err);
+                }
+                None
+            }
+        };
+        let dictionaries = encoders
+            .iter()
+            .filter_map(|loaded| loaded.dictionary.as_ref())
+            .filter_map(|dictionary| {
+                let contents = std::fs::read(dictionary).ok()?;
+                Some((dictionary.clone(), hash(&contents)))
+            })
+            .collect();
+        Self {
+            path: path.to_path_buf(),
+            loaded: previous.is_some(),
+            previous: previous.unwrap_or_default(),
+            current: Mutex::new(HashMap::new()),
+            dictionaries,
+        }
+    }
+
+    /// The key for a source with the contents `original`, compressed at
+    /// `level` into each of `outputs`
+    pub(crate) fn key<'a, M: AsRef<str> + Send>(
+        &self,
+        original: &[u8],
+        level: CompressionLevel,
+        outputs: impl IntoIterator<Item = (&'a LoadedEncoder<'a>, &'a Path)>,
+        options: &CompressionOptions<M>,
+    ) -> CacheKey {
+        let mut settings = Some(format!(
+            "{:?} {} {}",
+            level, options.skip_if_larger, options.min_ratio
+        ));
+        for (loaded, output) in outputs {
+            // Custom encoders can only be told apart by their suffix
+            let encoder = match &loaded.algorithm {
+                Some(algorithm) if loaded.compressor.is_some() => format!("{:?} custom", algorithm),
+                Some(algorithm) => format!("{:?}", algorithm),
+                None => loaded.encoder.suffix().to_string(),
+            };
+            let dictionary = match &loaded.dictionary {
+                Some(dictionary) => self.dictionaries.get(dictionary).map(String::as_str),
+                None => Some(""),
+            };
+            settings = settings.zip(dictionary).map(|(settings, dictionary)| {
+                format!(
+                    "{}; {} {} {}",
+                    settings,
+                    output.display(),
+                    encoder,
+                    dictionary
+                )
+            });
+        }
+        CacheKey {
+            hash: hash(original),
+            settings,
+        }
+    }
+
+    /// Whether `file` was compressed with the same `key` before and all of
+    /// its outputs are still as they were written. If so, its entry is kept
+    /// for the next run.
+    pub(crate) fn reuse(&self, file: &Path, key: &CacheKey) -> bool {
+        let Some(previous) = self.previous.get(file) else {
+            return false;
+        };
+        let fresh = key.settings.as_ref() == Some(&previous.settings)
+            && key.hash == previous.hash
+            && previous.outputs.iter().all(|(output, output_hash)| {
+                std::fs::read(output).is_ok_and(|contents| hash(&contents) == *output_hash)
+            });
+        if fresh {
+            self.record(file, key, previous.outputs.clone());
+        }
+        fresh
+    }
+
+    /// Whether the last run wrote `output` as a compressed file of `file`.
+    /// `None` if there's no cache from an earlier run to tell from.
+    pub(crate) fn wrote(&self, file: &Path, output: &Path) -> Option<bool> {
+        if !self.loaded {
+            return None;
+        }
+        let output = normalize_path(output);
+        let wrote = self
+            .previous
+            .get(&normalize_path(file))
+            .is_some_and(|entry| {
+                let written = entry.outputs.iter().map(|(written, _)| written);
+                written
+                    .chain(&entry.stale)
+                    .any(|written| normalize_path(written) == output)
+            });
+        Some(wrote)
+    }
+
+    /// Records that `file` was compressed into `outputs`, with their hashes
+    pub(crate) fn record(&self, file: &Path, key: &CacheKey, outputs: Vec<(PathBuf, String)>) {
+        let Some(settings) = &key.settings else {
+            return;
+        };
+        let stale = self
+            .previous
+            .get(file)
+            .map(|previous| {
+                let written = previous.outputs.iter().map(|(written, _)| written);
+                written
+                    .chain(&previous.stale)
+                    .filter(|written| outputs.iter().all(|(output, _)| output != *written))
+                    .filter(|written| written.is_file())
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        let entry = Entry {
+            hash: key.hash.clone(),
+            settings: settings.clone(),
+            outputs,
+            stale,
+        };
+        self.current
+            .lock()
+            .unwrap()
+            .insert(file.to_path_buf(), entry);
+    }
+
+    /// Replaces the cache file with the entries recorded in this run
+    pub(crate) fn save(self) -> Result<(), CompressionError> {
+        let mut current = self.current.into_inner().unwrap();
+        // Sources that weren't compressed this run, e.g. because they're below
+        // `min_size` now, keep their entries so their outputs are still known
+        for (file, entry) in self.previous {
+            if !current.contains_key(&file) && file.is_file() {
+                current.insert(file, entry);
+            }
+        }
+        let files = current
+            .into_iter()
+            .map(|(file, entry)| {
+                let outputs = entry
+                    .outputs
+                    .iter()
+                    .map(|(output, hash)| json!({ "path": output.to_string_lossy(), "hash": hash }))
+                    .collect::<Vec<_>>();
+                let entry = json!({
+                    "hash": entry.hash,
+                    "settings": entry.settings,
+                    "outputs": outputs,
+                    "stale": entry.stale,
+                });
+                (file.to_string_lossy().into_owned(), entry)
+            })
+            .collect::<serde_json::Map<_, _>>();
+        let cache = json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "files": files,
+        });
+        let json = serde_json::to_vec(&cache)
+            .map_err(std::io::Error::from)
+            .map_err(CompressionError::io(&self.path))?;
+        write_output(&self.path, &json)
+    }
+}
+
+/// The SHA-256 of a source or compressed file as hex
+pub(crate) fn hash(contents: &[u8]) -> String {
+    hex(&Sha256::digest(contents))
+}
+
+/// Parses a cache file, returning `None` if it's corrupt or was written by
+/// another version of the plugin
+fn parse(contents: &[u8]) -> Option<HashMap<PathBuf, Entry>> {
+    let cache = serde_json::from_slice::<Value>(contents).ok()?;
+    if cache.get("version")?.as_str()? != env!("CARGO_PKG_VERSION") {
+        return None;
+    }
+    cache
+        .get("files")?
+        .as_object()?
+        .iter()
+        .map(|(file, entry)| {
+            let outputs = entry
+                .get("outputs")?
+                .as_array()?
+                .iter()
+                .map(|output| {
+                    let path = output.get("path")?.as_str()?;
+                    let hash = output.get("hash")?.as_str()?;
+                    Some((PathBuf::from(path), hash.to_string()))
+                })
+                .collect::<Option<_>>()?;
+            let stale = entry
+                .get("stale")?
+                .as_array()?
+                .iter()
+                .map(|path| Some(PathBuf::from(path.as_str()?)))
+                .collect::<Option<_>>()?;
+            let entry = Entry {
+                hash: entry.get("hash")?.as_str()?.to_string(),
+                settings: entry.get("settings")?.as_str()?.to_string(),
+                outputs,
+                stale,
+            };
+            Some((normalize_path(Path::new(file)), entry))
+        })
+        .collect()
+}
@@ -0,0 +1,242 @@
+use crate::*;
+
+impl CompressionOptions<String> {
+    /// Checks that every glob parses and, on the server, that the algorithms'
+    /// parameters and levels are in range
+    pub(crate) fn validate(&self) -> Result<(), ConfigError> {
+        let globs = self
+            .include
+            .iter()
+            .chain(&self.exclude)
+            .chain(self.overrides.iter().map(|(glob, _)| glob));
+        for glob in globs {
+            glob::Pattern::new(glob).map_err(|source| ConfigError::InvalidGlob {
+                pattern: glob.clone(),
+                source,
+            })?;
+        }
+        #[cfg(all(engine, feature = "regex"))]
+        self.regexes().map_err(ConfigError::InvalidOption)?;
+        #[cfg(engine)]
+        self.checked_buffer_size()
+            .map_err(ConfigError::InvalidOption)?;
+        #[cfg(all(engine, feature = "parallel"))]
+        self.checked_max_in_flight_bytes()
+            .map_err(ConfigError::InvalidOption)?;
+        #[cfg(engine)]
+        for algorithm in self.all_algorithms() {
+            for level in self.levels() {
+                algorithm
+                    .validate(level)
+                    .map_err(ConfigError::InvalidOption)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(engine)]
+impl CompressionOptions<String> {
+    /// Loads options from environment variables, so CI can change them
+    /// without touching the source. Variables that aren't set keep their
+    /// defaults.
+    ///
+    /// * `PERSEUS_COMPRESS_INCLUDE`: comma-separated include globs
+    /// * `PERSEUS_COMPRESS_EXCLUDE`: comma-separated exclude globs, which
+    ///   replace the default exclude for source maps
+    /// * `PERSEUS_COMPRESS_QUALITY`: the level of every algorithm. It has to
+    ///   be in the range of each of them, so with the default algorithms `11`
+    ///   is an error, since gzip only goes up to 9
+    /// * `PERSEUS_COMPRESS_ENABLED`: `should_run`, `true`/`false`, `1`/`0` or
+    ///   `yes`/`no`
+    /// * `PERSEUS_COMPRESS_FORCE`: `force`, in the same format
+    ///
+    /// The options are validated like `CompressionOptionsBuilder::build` does.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let mut options = Self::converted_defaults();
+        if let Some(include) = env_var("PERSEUS_COMPRESS_INCLUDE")? {
+            options.include = split_list(&include);
+        }
+        if let Some(exclude) = env_var("PERSEUS_COMPRESS_EXCLUDE")? {
+            options.exclude = split_list(&exclude);
+        }
+        if let Some(value) = env_var("PERSEUS_COMPRESS_QUALITY")? {
+            let invalid = || invalid_env_var("PERSEUS_COMPRESS_QUALITY", &value);
+            let quality = value.trim().parse().map_err(|_| invalid())?;
+            options.algorithms = options
+                .algorithms
+                .into_iter()
+                .map(|algorithm| with_quality(algorithm, quality))
+                .collect::<Option<_>>()
+                .ok_or_else(invalid)?;
+        }
+        if let Some(enabled) = env_var("PERSEUS_COMPRESS_ENABLED")? {
+            options.should_run = parse_bool("PERSEUS_COMPRESS_ENABLED", &enabled)?.into();
+        }
+        if let Some(force) = env_var("PERSEUS_COMPRESS_FORCE")? {
+            options.force = parse_bool("PERSEUS_COMPRESS_FORCE", &force)?;
+        }
+        options.validate()?;
+        Ok(options)
+    }
+}
+
+/// `algorithm` at `quality`, or `None` if its level doesn't go that far
+#[cfg(engine)]
+#[cfg_attr(
+    not(any(
+        feature = "brotli",
+        feature = "gzip",
+        feature = "deflate",
+        feature = "zstd",
+        feature = "xz"
+    )),
+    allow(unused_variables, unreachable_patterns)
+)]
+fn with_quality(algorithm: Algorithm, quality: u32) -> Option<Algorithm> {
+    match algorithm {
+        #[cfg(feature = "brotli")]
+        Algorithm::Brotli(params) if quality <= 11 => {
+            Some(Algorithm::Brotli(BrotliParams { quality, ..params }))
+        }
+        #[cfg(feature = "gzip")]
+        Algorithm::Gzip(params) if quality <= 9 => Some(Algorithm::Gzip(GzipParams {
+            level: quality,
+            ..params
+        })),
+        #[cfg(feature = "deflate")]
+        Algorithm::Deflate(_) if quality <= 9 => {
+            Some(Algorithm::Deflate(DeflateParams { level: quality }))
+        }
+        #[cfg(feature = "zstd")]
+        Algorithm::Zstd(_) if (1..=22).contains(&quality) => Some(Algorithm::Zstd(ZstdParams {
+            level: quality as i32,
+        })),
+        #[cfg(feature = "xz")]
+        Algorithm::Xz(_) if quality <= 9 => Some(Algorithm::Xz(XzParams { preset: quality })),
+        _ => None,
+    }
+}
+
+/// Reads an environment variable, treating a missing one as unset
+#[cfg(engine)]
+fn env_var(name: &'static str) -> Result<Option<String>, ConfigError> {
+    match std::env::var(name) {
+        Ok(value) => Ok(Some(value)),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(std::env::VarError::NotUnicode(value)) => {
+            Err(invalid_env_var(name, &value.to_string_lossy()))
+        }
+    }
+}
+
+/// Parses a boolean environment variable
+#[cfg(engine)]
+fn parse_bool(name: &'static str, value: &str) -> Result<bool, ConfigError> {
+    match value.trim().to_lowercase().as_str() {
+        "true" | "1" | "yes" => Ok(true),
+        "false" | "0" | "no" => Ok(false),
+        _ => Err(invalid_env_var(name, value)),
+    }
+}
+
+#[cfg(engine)]
+fn invalid_env_var(name: &'static str, value: &str) -> ConfigError {
+    ConfigError::InvalidEnvVar {
+        name,
+        value: value.to_string(),
+    }
+}
+
+/// Splits a comma-separated list, ignoring empty items
+#[cfg(engine)]
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(ToString::to_string)
+        .collect()
+}
+
+#[cfg(all(engine, feature = "serde"))]
+impl CompressionOptions<String> {
+    /// Loads options from a TOML file, like a `perseus-compress.toml` checked
+    /// into your repository. Fields that aren't set keep their defaults, and
+    /// the options are validated like `CompressionOptionsBuilder::build` does.
+    ///
+    /// ```toml
+    /// include = ["./dist/static/**/*.css", "./dist/pkg/**/*.wasm"]
+    /// level = "best"
+    /// min_size = 512
+    /// algorithms = [{ brotli = { quality = 10 } }, { gzip = {} }]
+    /// ```
+    ///
+    /// Custom encoders and callbacks like `on_progress` can't be loaded from a
+    /// file and have to be set afterwards. Requires the `serde` feature.
+    pub fn from_toml_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents = read_config(path)?;
+        let options: Self = toml::from_str(&contents).map_err(|err| ConfigError::ParseFile {
+            path: path.to_path_buf(),
+            source: Box::new(err),
+        })?;
+        options.validate()?;
+        Ok(options)
+    }
+
+    /// Loads options from a JSON file, see `from_toml_file`. Requires the
+    /// `serde` feature.
+    pub fn from_json_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents = read_config(path)?;
+        let options: Self =
+            serde_json::from_str(&contents).map_err(|err| ConfigError::ParseFile {
+                path: path.to_path_buf(),
+                source: Box::new(err),
+            })?;
+        options.validate()?;
+        Ok(options)
+    }
+}
+
+#[cfg(all(engine, feature = "serde"))]
+fn read_config(path: &Path) -> Result<String, ConfigError> {
+    std::fs::read_to_string(path).map_err(|source| ConfigError::ReadFile {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+#[cfg(all(test, engine, feature = "brotli", feature = "gzip"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quality_out_of_any_algorithms_range_is_rejected() {
+        // The only test that sets this variable, so it can't race another
+        std::env::set_var("PERSEUS_COMPRESS_QUALITY", "11");
+        let too_high = CompressionOptions::from_env();
+        std::env::set_var("PERSEUS_COMPRESS_QUALITY", "9");
+        let options = CompressionOptions::from_env();
+        std::env::remove_var("PERSEUS_COMPRESS_QUALITY");
+
+        // Brotli accepts 11, but gzip only goes up to 9
+        assert!(matches!(
+            too_high,
+            Err(ConfigError::InvalidEnvVar {
+                name: "PERSEUS_COMPRESS_QUALITY",
+                ..
+            })
+        ));
+        let options = options.unwrap();
+        assert_eq!(options.level, CompressionLevel::Default);
+        assert!(options
+            .algorithms
+            .contains(&Algorithm::Brotli(BrotliParams {
+                quality: 9,
+                ..BrotliParams::default()
+            })));
+        assert!(options.algorithms.contains(&Algorithm::Gzip(GzipParams {
+            level: 9,
+            ..GzipParams::default()
+        })));
+    }
+}
@@ -0,0 +1,1355 @@
+use crate::*;
+use std::io::Write;
+
+/// A compression encoder. Implement this to compress files with an encoder
+/// that isn't built in, then add it to `CompressionOptions::encoders`. The
+/// built-in algorithms are implemented on top of this trait as well. To use
+/// only your own encoders, set `CompressionOptions::algorithms` to an empty
+/// list.
+///
+/// Encoders that can't stream, like an external program or a multi-pass
+/// strategy, can override `compress` instead of streaming through `wrap`.
+/// `wrap` is only called by the default `compress`, so it can just return
+/// an `std::io::ErrorKind::Unsupported` error then. Backends that only work on
+/// files can implement `Compressor` instead.
+pub trait Encoder {
+    /// The extension appended to compressed files, without the leading dot
+    fn suffix(&self) -> &str;
+    /// Wraps the output file in an encoder. Everything written to the returned
+    /// writer must end up compressed in `out` once the writer is dropped.
+    ///
+    /// `level` is the `CompressionOptions::level` and can be ignored if it
+    /// doesn't apply to this encoder.
+    fn wrap<'a>(
+        &self,
+        out: &'a mut dyn Write,
+        level: CompressionLevel,
+    ) -> std::io::Result<Box<dyn Write + 'a>>;
+    /// Whether decoding the output needs a dictionary besides the compressed
+    /// file itself. Recorded in `FileReport::dictionary`.
+    fn requires_dictionary(&self) -> bool {
+        false
+    }
+    /// The `Content-Encoding` a browser decodes the output with, used for
+    /// `CompressionOptions::generate_headers_file`. Outputs of encoders that
+    /// return `None` are left out of the headers file.
+    fn content_encoding(&self) -> Option<&str> {
+        None
+    }
+    /// Like `wrap`, but with the size of the source in bytes, which encoders
+    /// can use to tune themselves for large files. Calls `wrap` by default.
+    fn wrap_sized<'a>(
+        &self,
+        out: &'a mut dyn Write,
+        level: CompressionLevel,
+        size: u64,
+    ) -> std::io::Result<Box<dyn Write + 'a>> {
+        let _ = size;
+        self.wrap(out, level)
+    }
+    /// Compresses a whole source into memory. Errors fail the source like
+    /// any other. Streams `input` through `wrap_sized` by default.
+    fn compress(&self, input: &[u8], level: CompressionLevel) -> std::io::Result<Vec<u8>> {
+        let mut compressed = Vec::new();
+        self.wrap_sized(&mut compressed, level, input.len() as u64)
+            // Dropping the encoder finishes the stream
+            .and_then(|mut writer| writer.write_all(input))?;
+        Ok(compressed)
+    }
+    /// Decompresses an output of this encoder, used to check it with
+    /// `CompressionOptions::verify`. Returns `None` if the encoder can't
+    /// decompress, in which case its outputs aren't verified.
+    fn decode(&self, compressed: &[u8]) -> Option<std::io::Result<Vec<u8>>> {
+        let _ = compressed;
+        None
+    }
+}
+
+impl<E: Encoder + ?Sized> Encoder for &E {
+    fn suffix(&self) -> &str {
+        (**self).suffix()
+    }
+
+    fn wrap<'a>(
+        &self,
+        out: &'a mut dyn Write,
+        level: CompressionLevel,
+    ) -> std::io::Result<Box<dyn Write + 'a>> {
+        (**self).wrap(out, level)
+    }
+
+    fn requires_dictionary(&self) -> bool {
+        (**self).requires_dictionary()
+    }
+
+    fn content_encoding(&self) -> Option<&str> {
+        (**self).content_encoding()
+    }
+
+    fn wrap_sized<'a>(
+        &self,
+        out: &'a mut dyn Write,
+        level: CompressionLevel,
+        size: u64,
+    ) -> std::io::Result<Box<dyn Write + 'a>> {
+        (**self).wrap_sized(out, level, size)
+    }
+
+    fn compress(&self, input: &[u8], level: CompressionLevel) -> std::io::Result<Vec<u8>> {
+        (**self).compress(input, level)
+    }
+
+    fn decode(&self, compressed: &[u8]) -> Option<std::io::Result<Vec<u8>>> {
+        (**self).decode(compressed)
+    }
+}
+
+/// A compression backend that works on files rather than streams, like an
+/// external program, another version of a library or a multi-pass strategy.
+/// Set it as `CompressionOptions::custom_compressor` to compress with it
+/// instead of the built-in encoder of the first algorithm.
+///
+/// ```no_run
+/// use perseus_compress::{CompressionError, Compressor};
+/// use std::path::Path;
+///
+/// struct BrotliCli;
+///
+/// impl Compressor for BrotliCli {
+///     fn compress(&self, input: &Path, output: &Path) -> Result<(), CompressionError> {
+///         std::process::Command::new("brotli")
+///             .arg("--best")
+///             .arg("--output")
+///             .arg(output)
+///             .arg(input)
+///             .status()
+///             .and_then(|status| match status.success() {
+///                 true => Ok(()),
+///                 false => Err(std::io::Error::other(status.to_string())),
+///             })
+///             .map_err(|source| CompressionError::IoError {
+///                 path: input.to_path_buf(),
+///                 source,
+///             })
+///     }
+/// }
+/// ```
+pub trait Compressor: Send + Sync {
+    /// Compresses the file at `input` into a new file at `output`, which is
+    /// a temporary file the plugin reads back and removes afterwards
+    fn compress(
+        &self,
+        input: &std::path::Path,
+        output: &std::path::Path,
+    ) -> Result<(), CompressionError>;
+}
+
+/// An encoder files are compressed with
+#[cfg(engine)]
+pub(crate) struct LoadedEncoder<'a> {
+    pub(crate) encoder: Box<dyn Encoder + Send + Sync + 'a>,
+    /// `CompressionOptions::custom_compressor` if it compresses in place of
+    /// `encoder`, which then only names, serves and decodes the outputs
+    pub(crate) compressor: Option<&'a dyn Compressor>,
+    /// The algorithm the encoder compresses with, `None` for custom encoders
+    /// which every file is compressed with
+    pub(crate) algorithm: Option<Algorithm>,
+    /// The dictionary file the encoder compresses against. Outputs older than
+    /// it are recompressed.
+    pub(crate) dictionary: Option<PathBuf>,
+    /// Encoders used instead of `encoder` for sources with one of the
+    /// extensions, for `BrotliMode::Auto`. They all have the same suffix.
+    pub(crate) by_extension: Vec<(&'static [&'static str], Box<dyn Encoder + Send + Sync + 'a>)>,
+}
+
+#[cfg(engine)]
+impl<'a> LoadedEncoder<'a> {
+    /// Whether files compressed with `algorithms` are compressed with this
+    /// encoder
+    pub(crate) fn used_for(&self, algorithms: &[Algorithm]) -> bool {
+        self.algorithm
+            .is_none_or(|algorithm| algorithms.contains(&algorithm))
+    }
+
+    /// Compresses `file`, whose contents are `original`, into memory, naming
+    /// `output` in errors. The custom compressor writes to a temporary file,
+    /// so outputs that don't save enough space are still never written.
+    pub(crate) fn compress(
+        &self,
+        file: &Path,
+        original: &[u8],
+        level: CompressionLevel,
+        output: &Path,
+    ) -> Result<Vec<u8>, CompressionError> {
+        let Some(compressor) = self.compressor else {
+            return self
+                .encoder_for(file)
+                .compress(original, level)
+                .map_err(CompressionError::io(output));
+        };
+        static NEXT_TEMP: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let temp = std::env::temp_dir().join(format!(
+            ".perseus-compress-{}-{}.tmp",
+            std::process::id(),
+            NEXT_TEMP.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+        ));
+        let compressed = compressor
+            .compress(file, &temp)
+            .and_then(|()| std::fs::read(&temp).map_err(CompressionError::io(&temp)));
+        // The compressor may have failed before creating it
+        let _ = std::fs::remove_file(&temp);
+        compressed
+    }
+
+    /// The encoder `file` is compressed with
+    pub(crate) fn encoder_for(&self, file: &Path) -> &(dyn Encoder + Send + Sync + 'a) {
+        let extension = file
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        self.by_extension
+            .iter()
+            .find(|(extensions, _)| {
+                extension
+                    .as_deref()
+                    .is_some_and(|extension| extensions.contains(&extension))
+            })
+            .map_or(&*self.encoder, |(_, encoder)| &**encoder)
+    }
+}
+
+/// Sources `BrotliMode::Auto` compresses in `BrotliMode::Text`
+#[cfg(all(engine, feature = "brotli"))]
+const BROTLI_TEXT_EXTENSIONS: &[&str] = &[
+    "html", "htm", "css", "js", "mjs", "json", "map", "svg", "xml", "txt",
+];
+/// Sources `BrotliMode::Auto` compresses in `BrotliMode::Font`
+#[cfg(all(engine, feature = "brotli"))]
+const BROTLI_FONT_EXTENSIONS: &[&str] = &["woff2"];
+
+/// Collects the encoders files are compressed with, loading or training
+/// any dictionaries up front so it only happens once per run. The algorithms
+/// must already be validated.
+#[cfg(engine)]
+pub(crate) fn load_encoders<'a, M: AsRef<str> + Send>(
+    options: &'a CompressionOptions<M>,
+    #[cfg_attr(not(feature = "zstd"), allow(unused_variables))] files: &[PathBuf],
+) -> Result<Vec<LoadedEncoder<'a>>, CompressionError> {
+    let buffer_size = options.checked_buffer_size()?;
+    #[cfg(feature = "brotli")]
+    let brotli_dictionary = options
+        .brotli_dictionary
+        .as_ref()
+        .map(|path| {
+            std::fs::read(path)
+                .map(|dictionary| (std::sync::Arc::<[u8]>::from(dictionary), path))
+                .map_err(CompressionError::io(path))
+        })
+        .transpose()?;
+    // A single huge file can be split across as many threads as files are
+    #[cfg(feature = "parallel")]
+    let threads = match options.threads {
+        Threads::Fixed(threads) => threads.max(1),
+        Threads::Auto => rayon::current_num_threads(),
+    };
+    #[cfg(not(feature = "parallel"))]
+    let threads = 1;
+    let all_algorithms = options.all_algorithms();
+    #[cfg(feature = "zstd")]
+    let zstd_dictionary = match options.zstd_train_dictionary {
+        Some(max_size)
+            if all_algorithms
+                .iter()
+                .any(|algorithm| matches!(algorithm, Algorithm::Zstd(_))) =>
+        {
+            train_zstd_dictionary(files, max_size, options)?
+        }
+        _ => None,
+    };
+
+    let load = |algorithm: Algorithm| {
+        #[cfg(feature = "brotli")]
+        if let (Algorithm::Brotli(params), Some((dictionary, path))) =
+            (algorithm, &brotli_dictionary)
+        {
+            return LoadedEncoder {
+                encoder: Box::new(BrotliDictionaryEncoder {
+                    params,
+                    dictionary: dictionary.clone(),
+                    buffer_size,
+                }),
+                compressor: None,
+                algorithm: Some(algorithm),
+                dictionary: Some(path.to_path_buf()),
+                by_extension: Vec::new(),
+            };
+        }
+        #[cfg(feature = "zstd")]
+        if let (Algorithm::Zstd(params), Some(dictionary)) = (algorithm, &zstd_dictionary) {
+            return LoadedEncoder {
+                encoder: Box::new(ZstdDictionaryEncoder {
+                    params,
+                    dictionary: dictionary.clone(),
+                }),
+                compressor: None,
+                algorithm: Some(algorithm),
+                dictionary: Some(options.in_base_dir(ZSTD_DICTIONARY_PATH)),
+                by_extension: Vec::new(),
+            };
+        }
+        LoadedEncoder {
+            encoder: Box::new(BufferedAlgorithm {
+                algorithm,
+                buffer_size,
+                threads,
+            }),
+            compressor: None,
+            algorithm: Some(algorithm),
+            dictionary: None,
+            by_extension: Vec::new(),
+        }
+    };
+    let custom_for = options.algorithms.first().copied();
+    let algorithms = all_algorithms.into_iter().map(|algorithm| {
+        if let (Some(compressor), true) =
+            (&options.custom_compressor, custom_for == Some(algorithm))
+        {
+            // The outputs are still named, served and verified as the
+            // algorithm's, without any of its dictionaries
+            return LoadedEncoder {
+                encoder: Box::new(algorithm),
+                compressor: Some(&**compressor),
+                algorithm: Some(algorithm),
+                dictionary: None,
+                by_extension: Vec::new(),
+            };
+        }
+        #[cfg_attr(not(feature = "brotli"), allow(unused_mut))]
+        let mut loaded = load(algorithm);
+        // Every mode `Auto` picks from is loaded up front, so dictionaries
+        // are still only read once
+        #[cfg(feature = "brotli")]
+        if let Algorithm::Brotli(
+            params @ BrotliParams {
+                mode: BrotliMode::Auto,
+                ..
+            },
+        ) = algorithm
+        {
+            loaded.by_extension = [
+                (BROTLI_TEXT_EXTENSIONS, BrotliMode::Text),
+                (BROTLI_FONT_EXTENSIONS, BrotliMode::Font),
+            ]
+            .into_iter()
+            .map(|(extensions, mode)| {
+                let params = BrotliParams { mode, ..params };
+                (extensions, load(Algorithm::Brotli(params)).encoder)
+            })
+            .collect();
+        }
+        loaded
+    });
+    let custom = options.encoders.iter().map(|encoder| LoadedEncoder {
+        encoder: Box::new(&**encoder),
+        compressor: None,
+        algorithm: None,
+        dictionary: None,
+        by_extension: Vec::new(),
+    });
+    Ok(algorithms.chain(custom).collect())
+}
+
+#[cfg(engine)]
+impl Encoder for Algorithm {
+    fn suffix(&self) -> &str {
+        self.extension()
+    }
+
+    fn wrap<'a>(
+        &self,
+        out: &'a mut dyn Write,
+        level: CompressionLevel,
+    ) -> std::io::Result<Box<dyn Write + 'a>> {
+        compressor(out, level, *self, DEFAULT_BUFFER_SIZE, None, 1)
+    }
+
+    fn wrap_sized<'a>(
+        &self,
+        out: &'a mut dyn Write,
+        level: CompressionLevel,
+        size: u64,
+    ) -> std::io::Result<Box<dyn Write + 'a>> {
+        compressor(out, level, *self, DEFAULT_BUFFER_SIZE, Some(size), 1)
+    }
+
+    fn content_encoding(&self) -> Option<&str> {
+        match *self {
+            #[cfg(feature = "brotli")]
+            Algorithm::Brotli(_) => Some("br"),
+            #[cfg(feature = "gzip")]
+            Algorithm::Gzip(_) => Some("gzip"),
+            // Raw DEFLATE rather than zlib, which browsers decode for
+            // `deflate` all the same
+            #[cfg(feature = "deflate")]
+            Algorithm::Deflate(_) => Some("deflate"),
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd(_) => Some("zstd"),
+            #[cfg(feature = "xz")]
+            Algorithm::Xz(_) => None,
+        }
+    }
+
+    fn decode(&self, compressed: &[u8]) -> Option<std::io::Result<Vec<u8>>> {
+        Some(read_all(decompressor(compressed, *self)))
+    }
+}
+
+/// An algorithm compressing with `CompressionOptions::buffer_size`
+#[cfg(engine)]
+struct BufferedAlgorithm {
+    algorithm: Algorithm,
+    buffer_size: usize,
+    /// How many threads a single file can be compressed on, see
+    /// `BrotliParams::multithread_threshold`
+    threads: usize,
+}
+
+#[cfg(engine)]
+impl Encoder for BufferedAlgorithm {
+    fn suffix(&self) -> &str {
+        self.algorithm.extension()
+    }
+
+    fn wrap<'a>(
+        &self,
+        out: &'a mut dyn Write,
+        level: CompressionLevel,
+    ) -> std::io::Result<Box<dyn Write + 'a>> {
+        compressor(
+            out,
+            level,
+            self.algorithm,
+            self.buffer_size,
+            None,
+            self.threads,
+        )
+    }
+
+    fn wrap_sized<'a>(
+        &self,
+        out: &'a mut dyn Write,
+        level: CompressionLevel,
+        size: u64,
+    ) -> std::io::Result<Box<dyn Write + 'a>> {
+        compressor(
+            out,
+            level,
+            self.algorithm,
+            self.buffer_size,
+            Some(size),
+            self.threads,
+        )
+    }
+
+    fn content_encoding(&self) -> Option<&str> {
+        self.algorithm.content_encoding()
+    }
+
+    fn compress(&self, input: &[u8], level: CompressionLevel) -> std::io::Result<Vec<u8>> {
+        // Compress huge files directly rather than through a writer, which
+        // can't return errors when it's dropped
+        #[cfg(feature = "brotli")]
+        #[allow(irrefutable_let_patterns)]
+        if let Algorithm::Brotli(params) = self.algorithm {
+            let size = input.len() as u64;
+            if brotli_multithreaded(params, size, self.threads) {
+                let params = brotli_params(level, params, Some(size));
+                return brotli_compress_multi(input.to_vec(), &params, self.threads);
+            }
+        }
+        let mut compressed = Vec::new();
+        self.wrap_sized(&mut compressed, level, input.len() as u64)
+            // Dropping the encoder finishes the stream
+            .and_then(|mut writer| writer.write_all(input))?;
+        Ok(compressed)
+    }
+
+    fn decode(&self, compressed: &[u8]) -> Option<std::io::Result<Vec<u8>>> {
+        self.algorithm.decode(compressed)
+    }
+}
+
+/// Reads a decompressor to the end
+#[cfg(engine)]
+fn read_all(reader: std::io::Result<impl std::io::Read>) -> std::io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    reader?.read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+/// Only brotli and the flate2 encoders take a buffer size, and only brotli
+/// uses the size of the source and compresses a file on several threads
+#[cfg(engine)]
+#[cfg_attr(not(feature = "brotli"), allow(unused_variables))]
+fn compressor<'a>(
+    file: &'a mut dyn Write,
+    level: CompressionLevel,
+    algorithm: Algorithm,
+    buffer_size: usize,
+    size: Option<u64>,
+    threads: usize,
+) -> std::io::Result<Box<dyn Write + 'a>> {
+    match algorithm {
+        #[cfg(feature = "brotli")]
+        Algorithm::Brotli(params) => {
+            brotli_compressor(file, level, params, buffer_size, size, threads)
+        }
+        #[cfg(feature = "gzip")]
+        Algorithm::Gzip(params) => gzip_compressor(file, level, params, buffer_size),
+        #[cfg(feature = "deflate")]
+        Algorithm::Deflate(params) => deflate_compressor(file, level, params, buffer_size),
+        #[cfg(feature = "zstd")]
+        Algorithm::Zstd(params) => zstd_compressor(file, level, params),
+        #[cfg(feature = "xz")]
+        Algorithm::Xz(params) => xz_compressor(file, level, params),
+    }
+}
+
+#[cfg(engine)]
+#[cfg_attr(
+    not(any(
+        feature = "brotli",
+        feature = "gzip",
+        feature = "deflate",
+        feature = "zstd",
+        feature = "xz"
+    )),
+    allow(unused_variables)
+)]
+fn decompressor(
+    compressed: &[u8],
+    algorithm: Algorithm,
+) -> std::io::Result<Box<dyn std::io::Read + '_>> {
+    match algorithm {
+        #[cfg(feature = "brotli")]
+        Algorithm::Brotli(_) => Ok(Box::new(brotli::Decompressor::new(compressed, 4096))),
+        #[cfg(feature = "gzip")]
+        Algorithm::Gzip(_) => Ok(Box::new(flate2::read::GzDecoder::new(compressed))),
+        #[cfg(feature = "deflate")]
+        Algorithm::Deflate(_) => Ok(Box::new(flate2::read::DeflateDecoder::new(compressed))),
+        #[cfg(feature = "zstd")]
+        Algorithm::Zstd(_) => Ok(Box::new(zstd::Decoder::new(compressed)?)),
+        #[cfg(feature = "xz")]
+        Algorithm::Xz(_) => Ok(Box::new(xz2::read::XzDecoder::new(compressed))),
+    }
+}
+
+#[cfg(all(engine, feature = "brotli"))]
+fn brotli_compressor(
+    file: &mut dyn Write,
+    level: CompressionLevel,
+    params: BrotliParams,
+    buffer_size: usize,
+    size: Option<u64>,
+    threads: usize,
+) -> std::io::Result<Box<dyn Write + '_>> {
+    if size.is_some_and(|size| brotli_multithreaded(params, size, threads)) {
+        return Ok(Box::new(BrotliMultiThreadedWriter {
+            out: file,
+            params: brotli_params(level, params, size),
+            buffer: Vec::new(),
+            threads,
+            finished: false,
+        }));
+    }
+    Ok(Box::new(brotli::CompressorWriter::with_params(
+        file,
+        buffer_size,
+        &brotli_params(level, params, size),
+    )))
+}
+
+/// Whether a source of `size` bytes is split across threads, see
+/// `BrotliParams::multithread_threshold`
+#[cfg(all(engine, feature = "brotli"))]
+fn brotli_multithreaded(params: BrotliParams, size: u64, threads: usize) -> bool {
+    threads > 1
+        && params
+            .multithread_threshold
+            .is_some_and(|threshold| size > threshold)
+}
+
+#[cfg(all(engine, feature = "brotli"))]
+fn brotli_params(
+    level: CompressionLevel,
+    params: BrotliParams,
+    size: Option<u64>,
+) -> brotli::enc::BrotliEncoderParams {
+    use brotli::enc::backward_references::BrotliEncoderMode;
+
+    let quality = match level {
+        CompressionLevel::Fastest => 1,
+        CompressionLevel::Default => params.quality,
+        CompressionLevel::Best => 11,
+        CompressionLevel::Custom(quality) => quality,
+    };
+    let mode = match params.mode {
+        // Sources `Auto` picks another mode for have their own encoders
+        BrotliMode::Generic | BrotliMode::Auto => BrotliEncoderMode::BROTLI_MODE_GENERIC,
+        BrotliMode::Text => BrotliEncoderMode::BROTLI_MODE_TEXT,
+        BrotliMode::Font => BrotliEncoderMode::BROTLI_MODE_FONT,
+    };
+    // Large files get the biggest window browsers can decode, so repetition
+    // far apart is still found
+    let large_size = size.filter(|size| {
+        params
+            .large_window_threshold
+            .is_some_and(|threshold| *size > threshold)
+    });
+    brotli::enc::BrotliEncoderParams {
+        quality: quality as i32,
+        lgwin: if large_size.is_some() {
+            24
+        } else {
+            params.lgwin as i32
+        },
+        size_hint: large_size.unwrap_or(0) as usize,
+        mode,
+        ..Default::default()
+    }
+}
+
+/// Brotli with a custom dictionary, used for `Algorithm::Brotli` when
+/// `CompressionOptions::brotli_dictionary` is set
+#[cfg(all(engine, feature = "brotli"))]
+struct BrotliDictionaryEncoder {
+    params: BrotliParams,
+    dictionary: std::sync::Arc<[u8]>,
+    buffer_size: usize,
+}
+
+#[cfg(all(engine, feature = "brotli"))]
+impl Encoder for BrotliDictionaryEncoder {
+    fn suffix(&self) -> &str {
+        Algorithm::Brotli(self.params).extension()
+    }
+
+    fn wrap<'a>(
+        &self,
+        out: &'a mut dyn Write,
+        level: CompressionLevel,
+    ) -> std::io::Result<Box<dyn Write + 'a>> {
+        self.wrap_sized(out, level, 0)
+    }
+
+    fn wrap_sized<'a>(
+        &self,
+        out: &'a mut dyn Write,
+        level: CompressionLevel,
+        size: u64,
+    ) -> std::io::Result<Box<dyn Write + 'a>> {
+        Ok(Box::new(BrotliDictionaryWriter {
+            out,
+            params: brotli_params(level, self.params, Some(size)),
+            dictionary: self.dictionary.clone(),
+            buffer: Vec::new(),
+            buffer_size: self.buffer_size,
+            finished: false,
+        }))
+    }
+
+    fn compress(&self, input: &[u8], level: CompressionLevel) -> std::io::Result<Vec<u8>> {
+        // Compress directly rather than through a writer, which can't return
+        // errors when it's dropped
+        let params = brotli_params(level, self.params, Some(input.len() as u64));
+        let mut compressed = Vec::new();
+        brotli_compress_with_dictionary(
+            input,
+            &mut compressed,
+            &params,
+            &self.dictionary,
+            self.buffer_size,
+        )?;
+        Ok(compressed)
+    }
+
+    fn requires_dictionary(&self) -> bool {
+        true
+    }
+
+    fn decode(&self, compressed: &[u8]) -> Option<std::io::Result<Vec<u8>>> {
+        Some(read_all(Ok(brotli::Decompressor::new_with_custom_dict(
+            compressed,
+            4096,
+            self.dictionary.to_vec().into(),
+        ))))
+    }
+}
+
+/// The streaming brotli writer doesn't support custom dictionaries, so this
+/// buffers the input and compresses it in one go when flushed or dropped.
+#[cfg(all(engine, feature = "brotli"))]
+struct BrotliDictionaryWriter<'a> {
+    out: &'a mut dyn Write,
+    params: brotli::enc::BrotliEncoderParams,
+    dictionary: std::sync::Arc<[u8]>,
+    buffer: Vec<u8>,
+    buffer_size: usize,
+    finished: bool,
+}
+
+#[cfg(all(engine, feature = "brotli"))]
+impl BrotliDictionaryWriter<'_> {
+    /// Compresses the buffered input into `out`, only the first time
+    fn finish(&mut self) -> std::io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        let input = std::mem::take(&mut self.buffer);
+        brotli_compress_with_dictionary(
+            &input,
+            &mut self.out,
+            &self.params,
+            &self.dictionary,
+            self.buffer_size,
+        )
+    }
+}
+
+#[cfg(all(engine, feature = "brotli"))]
+impl Write for BrotliDictionaryWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.finished {
+            return Err(std::io::Error::other(
+                "the brotli stream was already finished by a flush",
+            ));
+        }
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    /// Finishes the stream, so nothing can be written afterwards
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.finish()?;
+        self.out.flush()
+    }
+}
+
+#[cfg(all(engine, feature = "brotli"))]
+impl Drop for BrotliDictionaryWriter<'_> {
+    fn drop(&mut self) {
+        // Errors can't be returned from `drop`, same as
+        // `brotli::CompressorWriter`. Flush first to see them.
+        let _ = self.finish();
+    }
+}
+
+/// Compresses `input` against a custom `dictionary` into `out`
+#[cfg(all(engine, feature = "brotli"))]
+fn brotli_compress_with_dictionary(
+    mut input: &[u8],
+    mut out: &mut dyn Write,
+    params: &brotli::enc::BrotliEncoderParams,
+    dictionary: &[u8],
+    buffer_size: usize,
+) -> std::io::Result<()> {
+    use brotli::enc::{BrotliCompressCustomIoCustomDict, StandardAlloc};
+    use brotli::{IoReaderWrapper, IoWriterWrapper};
+
+    BrotliCompressCustomIoCustomDict(
+        &mut IoReaderWrapper(&mut input),
+        &mut IoWriterWrapper(&mut out),
+        &mut vec![0; buffer_size],
+        &mut vec![0; buffer_size],
+        params,
+        StandardAlloc::default(),
+        &mut |_: &mut _, _: &mut _, _, _: &mut _| (),
+        dictionary,
+        std::io::Error::from(std::io::ErrorKind::UnexpectedEof),
+    )
+    .map(|_| ())
+}
+
+/// Brotli can only split a file across threads when it has all of it, so
+/// this buffers the input and compresses it in one go when flushed or
+/// dropped. The parts are joined into a single standard brotli stream.
+#[cfg(all(engine, feature = "brotli"))]
+struct BrotliMultiThreadedWriter<'a> {
+    out: &'a mut dyn Write,
+    params: brotli::enc::BrotliEncoderParams,
+    buffer: Vec<u8>,
+    threads: usize,
+    finished: bool,
+}
+
+#[cfg(all(engine, feature = "brotli"))]
+impl BrotliMultiThreadedWriter<'_> {
+    /// Compresses the buffered input into `out`, only the first time
+    fn finish(&mut self) -> std::io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        let input = std::mem::take(&mut self.buffer);
+        let compressed = brotli_compress_multi(input, &self.params, self.threads)?;
+        self.out.write_all(&compressed)
+    }
+}
+
+#[cfg(all(engine, feature = "brotli"))]
+impl Write for BrotliMultiThreadedWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.finished {
+            return Err(std::io::Error::other(
+                "the brotli stream was already finished by a flush",
+            ));
+        }
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    /// Finishes the stream, so nothing can be written afterwards
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.finish()?;
+        self.out.flush()
+    }
+}
+
+#[cfg(all(engine, feature = "brotli"))]
+impl Drop for BrotliMultiThreadedWriter<'_> {
+    fn drop(&mut self) {
+        // Errors can't be returned from `drop`, same as
+        // `brotli::CompressorWriter`. Flush first to see them.
+        let _ = self.finish();
+    }
+}
+
+/// The input of a multi-threaded brotli compression, which needs to be owned
+/// to be shared with the threads
+#[cfg(all(engine, feature = "brotli"))]
+struct BrotliInput(Vec<u8>);
+
+#[cfg(all(engine, feature = "brotli"))]
+impl brotli::enc::SliceWrapper<u8> for BrotliInput {
+    fn slice(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Compresses `input` on `threads` threads into a single brotli stream
+#[cfg(all(engine, feature = "brotli"))]
+fn brotli_compress_multi(
+    input: Vec<u8>,
+    params: &brotli::enc::BrotliEncoderParams,
+    threads: usize,
+) -> std::io::Result<Vec<u8>> {
+    use brotli::enc::{
+        compress_multi_no_threadpool, BrotliEncoderMaxCompressedSizeMulti, Owned, SendAlloc,
+        StandardAlloc, UnionHasher,
+    };
+
+    let size = input.len();
+    let mut input = Owned::new(BrotliInput(input));
+    let mut output = vec![0; BrotliEncoderMaxCompressedSizeMulti(size, threads)];
+    let mut allocs = (0..threads)
+        .map(|_| SendAlloc::new(StandardAlloc::default(), UnionHasher::Uninit))
+        .collect::<Vec<_>>();
+    match compress_multi_no_threadpool(params, &mut input, &mut output, &mut allocs) {
+        Ok(compressed_size) => {
+            output.truncate(compressed_size);
+            Ok(output)
+        }
+        // The input is only handed back if none of the threads panicked
+        Err(_) => {
+            let BrotliInput(input) = input.unwrap_or(BrotliInput(Vec::new()));
+            if input.len() != size {
+                return Err(std::io::Error::other(
+                    "a brotli compression thread panicked",
+                ));
+            }
+            let mut output = Vec::new();
+            brotli::BrotliCompress(&mut &input[..], &mut output, params)?;
+            Ok(output)
+        }
+    }
+}
+
+#[cfg(all(engine, feature = "gzip"))]
+fn gzip_compressor(
+    file: &mut dyn Write,
+    level: CompressionLevel,
+    params: GzipParams,
+    buffer_size: usize,
+) -> std::io::Result<Box<dyn Write + '_>> {
+    use flate2::Compression;
+
+    #[cfg(feature = "zopfli")]
+    if params.backend == GzipBackend::Zopfli && level != CompressionLevel::Fastest {
+        let encoder = zopfli::GzipEncoder::new_buffered(
+            zopfli::Options::default(),
+            zopfli::BlockType::Dynamic,
+            file,
+        )?;
+        return Ok(Box::new(encoder));
+    }
+
+    let level = match level {
+        CompressionLevel::Fastest => Compression::fast(),
+        CompressionLevel::Default => Compression::new(params.level),
+        CompressionLevel::Best => Compression::best(),
+        CompressionLevel::Custom(level) => Compression::new(level),
+    };
+    // Leave the timestamp and file name out of the header so the same source
+    // always produces the same file, which keeps builds reproducible
+    let encoder = flate2::GzBuilder::new().mtime(0).write(file, level);
+    Ok(Box::new(std::io::BufWriter::with_capacity(
+        buffer_size,
+        encoder,
+    )))
+}
+
+#[cfg(all(engine, feature = "deflate"))]
+fn deflate_compressor(
+    file: &mut dyn Write,
+    level: CompressionLevel,
+    params: DeflateParams,
+    buffer_size: usize,
+) -> std::io::Result<Box<dyn Write + '_>> {
+    use flate2::Compression;
+    let level = match level {
+        CompressionLevel::Fastest => Compression::fast(),
+        CompressionLevel::Default => Compression::new(params.level),
+        CompressionLevel::Best => Compression::best(),
+        CompressionLevel::Custom(level) => Compression::new(level),
+    };
+    Ok(Box::new(std::io::BufWriter::with_capacity(
+        buffer_size,
+        flate2::write::DeflateEncoder::new(file, level),
+    )))
+}
+
+#[cfg(all(engine, feature = "zstd"))]
+fn zstd_compressor(
+    file: &mut dyn Write,
+    level: CompressionLevel,
+    params: ZstdParams,
+) -> std::io::Result<Box<dyn Write + '_>> {
+    Ok(Box::new(
+        zstd::Encoder::new(file, zstd_level(level, params))?.auto_finish(),
+    ))
+}
+
+#[cfg(all(engine, feature = "zstd"))]
+fn zstd_level(level: CompressionLevel, params: ZstdParams) -> i32 {
+    match level {
+        CompressionLevel::Fastest => 1,
+        CompressionLevel::Default => params.level,
+        CompressionLevel::Best => *zstd::compression_level_range().end(),
+        CompressionLevel::Custom(level) => level as i32,
+    }
+}
+
+/// Where the trained zstd dictionary is written, so the server can ship it
+#[cfg(all(engine, feature = "zstd"))]
+pub(crate) const ZSTD_DICTIONARY_PATH: &str = "./dist/compress-dictionary.zstd";
+/// Training needs a handful of samples to find anything worth sharing
+#[cfg(all(engine, feature = "zstd"))]
+const MIN_ZSTD_DICTIONARY_SAMPLES: usize = 8;
+
+/// Trains a zstd dictionary on `files` and, unless it's a dry run, writes it
+/// to `ZSTD_DICTIONARY_PATH`. Returns `None` if there are too few files or
+/// training fails, in which case files are compressed without a dictionary.
+#[cfg(all(engine, feature = "zstd"))]
+fn train_zstd_dictionary<M: AsRef<str> + Send>(
+    files: &[PathBuf],
+    max_size: usize,
+    options: &CompressionOptions<M>,
+) -> Result<Option<std::sync::Arc<[u8]>>, CompressionError> {
+    if files.len() < MIN_ZSTD_DICTIONARY_SAMPLES {
+        warn!(
+            options,
+            "only {} file(s) to train a zstd dictionary on, compressing without one",
+            files.len()
+        );
+        return Ok(None);
+    }
+    let samples = files
+        .iter()
+        .map(|file| std::fs::read(file).map_err(CompressionError::io(file)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let dictionary = match zstd::dict::from_samples(&samples, max_size) {
+        Ok(dictionary) => dictionary,
+        Err(err) => {
+            warn!(
+                options,
+                "failed to train zstd dictionary, compressing without one: {}", err
+            );
+            return Ok(None);
+        }
+    };
+
+    // Only write the dictionary when it changed, so outputs compressed against
+    // an unchanged dictionary stay up to date
+    let path = options.in_base_dir(ZSTD_DICTIONARY_PATH);
+    if !options.dry_run && std::fs::read(&path).ok().as_deref() != Some(&dictionary[..]) {
+        write_file(&path, &dictionary).map_err(CompressionError::io(path))?;
+    }
+    Ok(Some(dictionary.into()))
+}
+
+/// Zstd with a trained dictionary, used for `Algorithm::Zstd` when
+/// `CompressionOptions::zstd_train_dictionary` is set
+#[cfg(all(engine, feature = "zstd"))]
+struct ZstdDictionaryEncoder {
+    params: ZstdParams,
+    dictionary: std::sync::Arc<[u8]>,
+}
+
+#[cfg(all(engine, feature = "zstd"))]
+impl Encoder for ZstdDictionaryEncoder {
+    fn suffix(&self) -> &str {
+        Algorithm::Zstd(self.params).extension()
+    }
+
+    fn wrap<'a>(
+        &self,
+        out: &'a mut dyn Write,
+        level: CompressionLevel,
+    ) -> std::io::Result<Box<dyn Write + 'a>> {
+        let level = zstd_level(level, self.params);
+        Ok(Box::new(
+            zstd::Encoder::with_dictionary(out, level, &self.dictionary)?.auto_finish(),
+        ))
+    }
+
+    fn requires_dictionary(&self) -> bool {
+        true
+    }
+
+    fn decode(&self, compressed: &[u8]) -> Option<std::io::Result<Vec<u8>>> {
+        Some(read_all(zstd::Decoder::with_dictionary(
+            compressed,
+            &self.dictionary,
+        )))
+    }
+}
+
+#[cfg(all(engine, feature = "xz"))]
+fn xz_compressor(
+    file: &mut dyn Write,
+    level: CompressionLevel,
+    params: XzParams,
+) -> std::io::Result<Box<dyn Write + '_>> {
+    let preset = match level {
+        CompressionLevel::Fastest => 0,
+        CompressionLevel::Default => params.preset,
+        CompressionLevel::Best => 9,
+        CompressionLevel::Custom(preset) => preset,
+    };
+    Ok(Box::new(xz2::write::XzEncoder::new(file, preset)))
+}
+
+#[cfg(all(
+    test,
+    engine,
+    any(
+        feature = "brotli",
+        feature = "gzip",
+        feature = "deflate",
+        feature = "zstd",
+        feature = "xz"
+    )
+))]
+mod tests {
+    use super::*;
+
+    /// A stylesheet with `rules` rules, varied enough that the level makes a
+    /// difference
+    fn css(rules: usize) -> Vec<u8> {
+        (0..rules)
+            .map(|i| {
+                format!(
+                    ".c{} {{ margin: {}px {}em; color: #{:06x}; }}\n",
+                    i,
+                    i % 37,
+                    i % 11,
+                    i * 7919 % 0xffffff
+                )
+            })
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    /// Compresses `input` and checks that it decodes to it again
+    fn roundtrip(encoder: &dyn Encoder, input: &[u8], level: CompressionLevel) -> Vec<u8> {
+        let compressed = encoder.compress(input, level).unwrap();
+        let decoded = encoder.decode(&compressed).unwrap().unwrap();
+        assert_eq!(decoded, input, "{} didn't roundtrip", encoder.suffix());
+        compressed
+    }
+
+    #[test]
+    fn every_algorithm_roundtrips() {
+        let input = css(2000);
+        for algorithm in Algorithm::enabled() {
+            for level in [CompressionLevel::Fastest, CompressionLevel::Default] {
+                let compressed = roundtrip(&algorithm, &input, level);
+                assert!(compressed.len() < input.len());
+            }
+        }
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_level_0_is_larger_than_level_9() {
+        let gzip = Algorithm::Gzip(GzipParams::default());
+        let input = css(2000);
+        let level_0 = roundtrip(&gzip, &input, CompressionLevel::Custom(0));
+        let level_9 = roundtrip(&gzip, &input, CompressionLevel::Custom(9));
+        assert!(level_0.len() > level_9.len());
+    }
+
+    #[cfg(feature = "zopfli")]
+    #[test]
+    fn zopfli_is_smaller_than_flate2() {
+        let input = css(500);
+        let flate2 = Algorithm::Gzip(GzipParams {
+            level: 9,
+            backend: GzipBackend::Flate2,
+        });
+        let zopfli = Algorithm::Gzip(GzipParams {
+            level: 9,
+            backend: GzipBackend::Zopfli,
+        });
+        let flate2 = roundtrip(&flate2, &input, CompressionLevel::Default);
+        let zopfli = roundtrip(&zopfli, &input, CompressionLevel::Default);
+        assert!(zopfli.len() < flate2.len());
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn deflate_writes_a_raw_deflate_stream() {
+        use std::io::Read;
+
+        let input = css(2000);
+        let deflate = Algorithm::Deflate(DeflateParams::default());
+        let compressed = deflate.compress(&input, CompressionLevel::Default).unwrap();
+        let mut decoded = Vec::new();
+        flate2::read::DeflateDecoder::new(&compressed[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, input);
+    }
+
+    #[cfg(feature = "xz")]
+    #[test]
+    fn xz_roundtrips_at_every_preset() {
+        use std::io::Read;
+
+        let input = css(2000);
+        for preset in [0, 6, 9] {
+            let xz = Algorithm::Xz(XzParams { preset });
+            let compressed = xz.compress(&input, CompressionLevel::Default).unwrap();
+            let mut decoded = Vec::new();
+            xz2::read::XzDecoder::new(&compressed[..])
+                .read_to_end(&mut decoded)
+                .unwrap();
+            assert_eq!(decoded, input, "preset {} didn't roundtrip", preset);
+        }
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn brotli_dictionary_output_decodes_with_the_dictionary() {
+        use std::io::Read;
+
+        let boilerplate = "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><link \
+            rel=\"stylesheet\" href=\"/.perseus/static/main.css\"></head><body><nav \
+            class=\"navbar\"><a href=\"/\">Home</a><a href=\"/about\">About</a></nav>";
+        let page = format!("{boilerplate}<main><h1>About</h1></main></body></html>");
+        let dictionary = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(dictionary.path(), boilerplate).unwrap();
+        let brotli = Algorithm::Brotli(BrotliParams::default());
+        let options = CompressionOptions::<String> {
+            algorithms: vec![brotli],
+            brotli_dictionary: Some(dictionary.path().to_path_buf()),
+            ..CompressionOptions::converted_defaults()
+        };
+
+        let encoders = load_encoders(&options, &[]).unwrap();
+        let encoder = encoders[0].encoder_for(Path::new("about.html"));
+        // Recorded in `FileReport::dictionary`
+        assert!(encoder.requires_dictionary());
+        let compressed = encoder
+            .compress(page.as_bytes(), CompressionLevel::Default)
+            .unwrap();
+        let without = brotli
+            .compress(page.as_bytes(), CompressionLevel::Default)
+            .unwrap();
+        assert!(compressed.len() < without.len());
+
+        let mut decoded = Vec::new();
+        brotli::Decompressor::new_with_custom_dict(
+            &compressed[..],
+            4096,
+            boilerplate.as_bytes().to_vec().into(),
+        )
+        .read_to_end(&mut decoded)
+        .unwrap();
+        assert_eq!(decoded, page.as_bytes());
+
+        // The writer finishes the stream on flush, the same as `compress`
+        let mut written = Vec::new();
+        let mut writer = encoder
+            .wrap_sized(&mut written, CompressionLevel::Default, page.len() as u64)
+            .unwrap();
+        writer.write_all(page.as_bytes()).unwrap();
+        writer.flush().unwrap();
+        assert!(writer.write_all(b"more").is_err());
+        drop(writer);
+        assert_eq!(written, compressed);
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn higher_brotli_quality_is_smaller() {
+        let input = css(2000);
+        let sizes = [1, 5, 11].map(|quality| {
+            let brotli = Algorithm::Brotli(BrotliParams {
+                quality,
+                ..BrotliParams::default()
+            });
+            roundtrip(&brotli, &input, CompressionLevel::Default).len()
+        });
+        assert!(sizes[0] >= sizes[1] && sizes[1] >= sizes[2], "{:?}", sizes);
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn invalid_brotli_quality_is_an_error() {
+        let brotli = Algorithm::Brotli(BrotliParams {
+            quality: 12,
+            ..BrotliParams::default()
+        });
+        assert!(matches!(
+            brotli.validate(CompressionLevel::Default),
+            Err(CompressionError::OutOfRange {
+                option: "brotli quality",
+                value: 12,
+                ..
+            })
+        ));
+        let brotli = Algorithm::Brotli(BrotliParams::default());
+        assert!(brotli.validate(CompressionLevel::Custom(12)).is_err());
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn brotli_mode_is_passed_to_the_encoder() {
+        use brotli::enc::backward_references::BrotliEncoderMode;
+
+        let modes = [
+            (BrotliMode::Generic, BrotliEncoderMode::BROTLI_MODE_GENERIC),
+            (BrotliMode::Text, BrotliEncoderMode::BROTLI_MODE_TEXT),
+            (BrotliMode::Font, BrotliEncoderMode::BROTLI_MODE_FONT),
+        ];
+        let input = css(2000);
+        for (mode, expected) in modes {
+            let params = BrotliParams {
+                mode,
+                ..BrotliParams::default()
+            };
+            let encoder_params = brotli_params(CompressionLevel::Default, params, None);
+            assert_eq!(encoder_params.mode, expected);
+            roundtrip(
+                &Algorithm::Brotli(params),
+                &input,
+                CompressionLevel::Default,
+            );
+        }
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_levels_decode_to_the_same_content() {
+        let input = css(2000);
+        let compressed = [1, 9].map(|level| {
+            let gzip = Algorithm::Gzip(GzipParams {
+                level,
+                ..GzipParams::default()
+            });
+            roundtrip(&gzip, &input, CompressionLevel::Default)
+        });
+        assert_ne!(compressed[0].len(), compressed[1].len());
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn large_window_finds_distant_repetition() {
+        // Two copies of 5 MiB of noise, further apart than the default window
+        let mut state = 1u64;
+        let noise = (0..5 << 20)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 56) as u8
+            })
+            .collect::<Vec<_>>();
+        let input = [&noise[..], &noise[..]].concat();
+        let params = BrotliParams {
+            quality: 9,
+            ..BrotliParams::default()
+        };
+        let large_window = BrotliParams {
+            large_window_threshold: Some(1 << 20),
+            ..params
+        };
+
+        let small_file = brotli_params(CompressionLevel::Default, large_window, Some(1024));
+        assert_eq!(small_file.lgwin, 22);
+        let default = Algorithm::Brotli(params)
+            .compress(&input, CompressionLevel::Default)
+            .unwrap();
+        let large = roundtrip(
+            &Algorithm::Brotli(large_window),
+            &input,
+            CompressionLevel::Default,
+        );
+        assert!(large.len() < default.len());
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn multithreaded_brotli_decodes_to_the_original() {
+        let input = css(20_000);
+        let algorithm = Algorithm::Brotli(BrotliParams {
+            quality: 5,
+            multithread_threshold: Some(1 << 16),
+            ..BrotliParams::default()
+        });
+        for threads in [1, 4] {
+            let encoder = BufferedAlgorithm {
+                algorithm,
+                buffer_size: DEFAULT_BUFFER_SIZE,
+                threads,
+            };
+            roundtrip(&encoder, &input, CompressionLevel::Default);
+
+            // Writers finish the stream on flush and return its errors
+            let mut compressed = Vec::new();
+            let mut writer = encoder
+                .wrap_sized(
+                    &mut compressed,
+                    CompressionLevel::Default,
+                    input.len() as u64,
+                )
+                .unwrap();
+            writer.write_all(&input).unwrap();
+            writer.flush().unwrap();
+            drop(writer);
+            assert_eq!(encoder.decode(&compressed).unwrap().unwrap(), input);
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use std::path::PathBuf;
+
+/// Errors that can occur while compressing files
+#[derive(Debug)]
+pub enum CompressionError {
+    /// A path matched by an include or exclude glob couldn't be read
+    GlobError(glob::GlobError),
+    /// An include, exclude or override glob couldn't be parsed
+    InvalidGlob {
+        /// The glob as it was given
+        pattern: String,
+        /// Why it couldn't be parsed
+        source: glob::PatternError,
+    },
+    /// Reading or writing a file failed
+    IoError {
+        /// The file that was being read or written
+        path: PathBuf,
+        /// The underlying error
+        source: std::io::Error,
+    },
+    /// A compressed file didn't decompress to its source, found by
+    /// `CompressionOptions::verify`
+    VerificationFailed {
+        /// The compressed file
+        path: PathBuf,
+        /// SHA-256 hash of the source
+        expected: String,
+        /// SHA-256 hash of the decompressed file
+        got: String,
+    },
+    /// An include or exclude regular expression couldn't be parsed
+    #[cfg(all(engine, feature = "regex"))]
+    InvalidRegex {
+        /// The expression as it was given
+        pattern: String,
+        /// Why it couldn't be parsed
+        source: regex::Error,
+    },
+    /// An include glob didn't match any files, found with
+    /// `CompressionOptions::fail_on_empty_glob`
+    EmptyGlob {
+        /// The glob as it was given
+        pattern: String,
+    },
+    /// An option is outside the range its encoder accepts
+    OutOfRange {
+        /// The option that's out of range
+        option: &'static str,
+        /// The configured value
+        value: i64,
+        /// The smallest allowed value
+        min: i64,
+        /// The largest allowed value
+        max: i64,
+    },
+    /// A matched file isn't in `CompressionOptions::output_root`, so there's
+    /// no place for it in `CompressionOptions::output_dir`
+    OutsideOutputRoot {
+        /// The matched file
+        path: PathBuf,
+        /// The configured root
+        root: PathBuf,
+    },
+    /// There is nothing to compress with. Enable one of the algorithm features
+    /// or add a custom encoder. The plugin checks this before the build
+    /// starts, so it fails right away.
+    NoAlgorithmEnabled,
+    /// One or more files failed to compress. Compression continues for the
+    /// remaining files, so this holds every failure rather than just the first.
+    FilesFailed(Vec<(PathBuf, CompressionError)>),
+    /// The thread pool for parallel compression couldn't be created
+    #[cfg(all(engine, feature = "parallel"))]
+    ThreadPool(rayon::ThreadPoolBuildError),
+}
+
+impl CompressionError {
+    /// Creates a closure that wraps an I/O error with the path it occurred on,
+    /// for use with `map_err`.
+    #[cfg(engine)]
+    pub(crate) fn io(path: impl Into<PathBuf>) -> impl FnOnce(std::io::Error) -> Self {
+        let path = path.into();
+        move |source| Self::IoError { path, source }
+    }
+
+    /// Checks that `value` is between `min` and `max`, inclusive
+    #[cfg(engine)]
+    pub(crate) fn check_range(
+        option: &'static str,
+        value: impl Into<i64>,
+        min: i64,
+        max: i64,
+    ) -> Result<(), Self> {
+        let value = value.into();
+        if (min..=max).contains(&value) {
+            Ok(())
+        } else {
+            Err(Self::OutOfRange {
+                option,
+                value,
+                min,
+                max,
+            })
+        }
+    }
+}
+
+impl std::fmt::Display for CompressionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::GlobError(err) => write!(f, "failed to read glob match: {}", err),
+            Self::InvalidGlob { pattern, source } => {
+                write!(f, "invalid glob \"{}\": {}", pattern, source)
+            }
+            Self::IoError { path, source } => write!(f, "{}: {}", path.display(), source),
+            Self::VerificationFailed {
+                path,
+                expected,
+                got,
+            } => write!(
+                f,
+                "{} doesn't decompress to its source, expected SHA-256 {} but got {}",
+                path.display(),
+                expected,
+                got
+            ),
+            #[cfg(all(engine, feature = "regex"))]
+            Self::InvalidRegex { pattern, source } => {
+                write!(f, "invalid regex \"{}\": {}", pattern, source)
+            }
+            Self::EmptyGlob { pattern } => {
+                write!(f, "include glob \"{}\" didn't match any files", pattern)
+            }
+            Self::OutOfRange {
+                option,
+                value,
+                min,
+                max,
+            } => write!(
+                f,
+                "{} must be between {} and {}, got {}",
+                option, min, max, value
+            ),
+            Self::OutsideOutputRoot { path, root } => write!(
+                f,
+                "{} is outside the output root {}",
+                path.display(),
+                root.display()
+            ),
+            Self::NoAlgorithmEnabled => write!(
+                f,
+                "no compression algorithm enabled, enable one of the `brotli`, `gzip`, `deflate`, `zstd` or `xz` features of perseus-compress or add a custom encoder"
+            ),
+            Self::FilesFailed(failures) => {
+                write!(f, "failed to compress {} file(s)", failures.len())?;
+                for (path, err) in failures {
+                    write!(f, "\n  {}: {}", path.display(), err)?;
+                }
+                Ok(())
+            }
+            #[cfg(all(engine, feature = "parallel"))]
+            Self::ThreadPool(err) => write!(f, "failed to create thread pool: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for CompressionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::GlobError(err) => Some(err),
+            Self::InvalidGlob { source, .. } => Some(source),
+            #[cfg(all(engine, feature = "regex"))]
+            Self::InvalidRegex { source, .. } => Some(source),
+            Self::IoError { source, .. } => Some(source),
+            #[cfg(all(engine, feature = "parallel"))]
+            Self::ThreadPool(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<glob::GlobError> for CompressionError {
+    fn from(err: glob::GlobError) -> Self {
+        Self::GlobError(err)
+    }
+}
+
+/// Errors in options found by `CompressionOptionsBuilder::build` or while
+/// loading them from a file or the environment
+#[derive(Debug)]
+pub enum ConfigError {
+    /// An include, exclude or override glob couldn't be parsed
+    InvalidGlob {
+        /// The glob as it was given
+        pattern: String,
+        /// Why it couldn't be parsed
+        source: glob::PatternError,
+    },
+    /// An option is invalid, e.g. an algorithm's parameters or a compression
+    /// level are out of range, as `CompressionError::OutOfRange`
+    InvalidOption(CompressionError),
+    /// A config file couldn't be read
+    ReadFile {
+        /// The config file
+        path: PathBuf,
+        /// The underlying error
+        source: std::io::Error,
+    },
+    /// A config file isn't valid TOML or JSON, or doesn't describe
+    /// `CompressionOptions`
+    ParseFile {
+        /// The config file
+        path: PathBuf,
+        /// The error from the parser
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// An environment variable read by `CompressionOptions::from_env` has a
+    /// value it doesn't understand
+    InvalidEnvVar {
+        /// The variable's name
+        name: &'static str,
+        /// Its value
+        value: String,
+    },
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidGlob { pattern, source } => {
+                write!(f, "invalid glob \"{}\": {}", pattern, source)
+            }
+            Self::InvalidOption(err) => write!(f, "{}", err),
+            Self::ReadFile { path, source } => {
+                write!(f, "failed to read {}: {}", path.display(), source)
+            }
+            Self::ParseFile { path, source } => {
+                write!(f, "failed to parse {}: {}", path.display(), source)
+            }
+            Self::InvalidEnvVar { name, value } => {
+                write!(f, "invalid value for {}: \"{}\"", name, value)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::InvalidGlob { source, .. } => Some(source),
+            Self::InvalidOption(err) => Some(err),
+            Self::ReadFile { source, .. } => Some(source),
+            Self::ParseFile { source, .. } => Some(source.as_ref()),
+            Self::InvalidEnvVar { .. } => None,
+        }
+    }
+}
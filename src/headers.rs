@@ -0,0 +1,105 @@
+//! The `_headers` file written to `CompressionOptions::generate_headers_file`
+
+use crate::encoder::LoadedEncoder;
+use crate::manifest::variants;
+use crate::{relative_to, replace_file, CompressionError, CompressionOptions};
+use std::fmt::Write;
+use std::path::{Component, Path, PathBuf};
+
+/// Writes a Netlify `_headers` file to `path` with a rule for every
+/// compressed file of the sources in `files` that's served from the
+/// directory the headers file is in
+pub(crate) fn write_headers<'a, M: AsRef<str> + Send>(
+    path: &Path,
+    files: impl Iterator<Item = &'a Path>,
+    encoders: &[LoadedEncoder],
+    options: &CompressionOptions<M>,
+    output_path: impl Fn(&Path) -> PathBuf,
+) -> Result<(), CompressionError> {
+    let root = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut headers = String::new();
+    for file in files {
+        let content_type = file
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(content_type);
+        for variant in variants(file, &output_path(file), encoders, options) {
+            let Some(encoding) = variant.encoder.content_encoding() else {
+                continue;
+            };
+            let Some(url) = url_path(root, &variant.path) else {
+                continue;
+            };
+            // Writing to a `String` can't fail
+            let _ = writeln!(headers, "{}", url);
+            let _ = writeln!(headers, "  Content-Encoding: {}", encoding);
+            if let Some(content_type) = content_type {
+                let _ = writeln!(headers, "  Content-Type: {}", content_type);
+            }
+        }
+    }
+    // The host reads the headers file on deploy, so never leave a partially
+    // written one behind
+    replace_file(path, headers.as_bytes())
+}
+
+/// The URL `file` is served at when `root` is the published directory, e.g.
+/// `/about/index.html.br` for `./dist/exported/about/index.html.br` when
+/// publishing `./dist/exported`. `None` if `file` isn't in `root`.
+///
+/// Index pages are served at their directory's URL, but their compressed
+/// files are only ever requested by their full name, so they need no special
+/// handling.
+fn url_path(root: &Path, file: &Path) -> Option<String> {
+    let relative = relative_to(root, file)?;
+    let segments = relative
+        .components()
+        .map(|component| match component {
+            Component::Normal(segment) => segment.to_str(),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(format!("/{}", segments.join("/")))
+}
+
+/// The `Content-Type` of a source with `extension`, which the compressed file
+/// would otherwise be served without
+fn content_type(extension: &str) -> Option<&'static str> {
+    let content_type = match extension.to_ascii_lowercase().as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "wasm" => "application/wasm",
+        "svg" => "image/svg+xml",
+        "xml" => "application/xml",
+        "txt" => "text/plain; charset=utf-8",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "eot" => "application/vnd.ms-fontobject",
+        _ => return None,
+    };
+    Some(content_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_path_keeps_nested_directories() {
+        let root = Path::new("./dist");
+        assert_eq!(
+            url_path(root, Path::new("dist/about/index.html.br")).as_deref(),
+            Some("/about/index.html.br")
+        );
+        assert_eq!(url_path(root, Path::new("static/main.css.br")), None);
+    }
+
+    #[test]
+    fn content_type_ignores_case() {
+        assert_eq!(content_type("CSS"), Some("text/css; charset=utf-8"));
+        assert_eq!(content_type("Wasm"), Some("application/wasm"));
+        assert_eq!(content_type("png"), None);
+    }
+}
@@ -1,9 +1,22 @@
 //! This is a simple plugin for Perseus that automatically compresses static
-//! files after each successful build. Use features to pick between the `brotli`
-//! and `gzip` compression algorithms. Brotli is recommended beacuse it's faster,
+//! files after each successful build. Use features to pick between the `brotli`,
+//! `gzip`, `deflate` and `zstd` compression algorithms. Brotli is recommended beacuse it's faster,
 //! produces smaller files and is supported in everything except Internet Explorer.
 //!
-//! It can be disabled in development with the `should_run` flag on `CompressionOptions`.
+//! It can be disabled in development with the `should_run` flag on `CompressionOptions`,
+//! which can also be a closure that's only called when the plugin runs, e.g. to
+//! check the `CI` environment variable, see `ShouldRun::lazy`.
+//!
+//! Files are always compressed before the build finishes. Perseus builds in a
+//! process that exits as soon as the build is done, so compressing in the
+//! background would leave files half written.
+//!
+//! If your development server only serves precompressed files, set `level` to
+//! `CompressionLevel::Fastest` in development instead. It produces the same
+//! files, just a little bigger, in a fraction of the time.
+//!
+//! For the smallest files before deploying, start from
+//! `CompressionOptions::with_profile(CompressionProfile::Smallest)`.
 //!
 //! # Usage
 //!
@@ -23,13 +36,69 @@
 //! ```
 //!
 //! If you're already using plugins just add the plugin to your `Plugins` as usual.
+//! Options can also be put together with `CompressionOptions::builder`.
+//! To compress files outside of a Perseus build, call `compress_paths` directly,
+//! or `compress_dir` to compress everything with some extensions under a
+//! directory without writing any globs. `clean_compressed_files` deletes the
+//! compressed files again.
+//!
+//! Algorithms are picked by enabling features in your `Cargo.toml`. If more
+//! than one is enabled, every file is compressed with each of them, so you can
+//! serve `.br` to modern browsers and fall back to `.gz` for older clients:
+//!
+//! ```toml
+//! perseus-compress = { version = "0.1", features = ["brotli", "gzip"] }
+//! ```
+//!
+//! For the smallest possible gzip files at the cost of much slower builds,
+//! enable the `zopfli` feature and set `GzipParams::backend` to
+//! `GzipBackend::Zopfli`.
+//!
+//! The `xz` feature produces `.xz` files. Browsers can't decode these, but
+//! they're useful for archiving build output alongside the other algorithms.
+//!
+//! Enable the `parallel` feature to compress files on all available cores, or
+//! on as many as `threads` allows.
+//!
+//! Enable the `tempfile` feature to write every file to a temporary file first
+//! and rename it into place, so an interrupted build never leaves a partially
+//! written file for your server to pick up.
+//!
+//! Enable the `verify` feature and set `verify` to decompress every file after
+//! compressing it and compare its SHA-256 hash with the source's.
+//!
+//! Enable the `cache` feature and set `cache` to a file to skip sources whose
+//! contents haven't changed since the last run, even if their mtimes did, e.g.
+//! when CI restores `dist` from a cache.
+//!
+//! Progress is logged through the `log` crate, at the verbosity set by
+//! `log_level`. Disable default features to leave out logging entirely.
 //!
 //! # Configuration
 //!
 //! Includes and excludes can be defined via file globs. For example,
 //! "./dist/static/**/*.css" would match all CSS files in the static output
 //! directory while "./dist/static/dont_compress.css" could exclude that specific
-//! file.
+//! file. Relative globs are resolved against the working directory, or
+//! `base_dir` if it's set. Source maps (`./dist/**/*.map`) are excluded by
+//! default.
+//!
+//! Exported HTML pages, Perseus' JSON state files and SVG images in the static
+//! directory are compressed too, unless `include_html` or `include_svg` is
+//! turned off. Set `include_fonts` to compress TTF, OTF and EOT fonts as well.
+//!
+//! For filters globs can't express, like names containing a content hash,
+//! enable the `regex` feature and use `include_regex` and `exclude_regex`.
+//! Files have to match both an include glob and, if there are any, an include
+//! regex, and are excluded by either kind of exclude.
+//!
+//! `CompressionOptions::from_env` reads the includes, excludes, level,
+//! `should_run` and `force` from `PERSEUS_COMPRESS_*` environment variables, so
+//! CI can change them without touching the source.
+//!
+//! With the `serde` feature, options can also be kept in a TOML or JSON file
+//! and loaded with `CompressionOptions::from_toml_file` or
+//! `CompressionOptions::from_json_file`.
 //!
 //! # Quirks
 //!
@@ -39,28 +108,209 @@
 //! site, make sure you do a clean build.
 //!
 
+#[cfg(engine)]
+#[macro_use]
+mod logging;
+
+mod builder;
+#[cfg(all(engine, feature = "cache"))]
+mod cache;
+mod config;
+mod encoder;
+mod error;
+#[cfg(engine)]
+mod headers;
+#[cfg(engine)]
+mod manifest;
+mod report;
+
+pub use builder::CompressionOptionsBuilder;
+#[cfg(engine)]
+use encoder::LoadedEncoder;
+pub use encoder::{Compressor, Encoder};
+pub use error::{CompressionError, ConfigError};
+#[cfg(engine)]
+use report::SourceOutcome;
+pub use report::{CompressionFileResult, CompressionReport, FileReport};
+
 use perseus::plugins::{empty_control_actions_registrar, Plugin, PluginEnv};
 #[cfg(engine)]
-use std::{
-    io::Write,
-    path::{Path, PathBuf},
-};
+use std::path::{Path, PathBuf};
+
+/// Buffer size used by `Algorithm`'s encoders unless
+/// `CompressionOptions::buffer_size` says otherwise
+#[cfg(engine)]
+const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The largest `CompressionOptions::buffer_size` that's accepted
+#[cfg(engine)]
+const MAX_BUFFER_SIZE: usize = 64 * 1024 * 1024;
+
+/// Where `CompressionOptions::write_report` writes the report
+#[cfg(all(engine, feature = "json"))]
+const REPORT_PATH: &str = "./dist/compress-report.json";
+
+/// Globs matched in addition to the includes with
+/// `CompressionOptions::include_html`
+#[cfg(engine)]
+const HTML_INCLUDES: &[&str] = &["./dist/exported/**/*.html", "./dist/**/*.json"];
+
+/// Globs matched in addition to the includes with
+/// `CompressionOptions::include_svg`
+#[cfg(engine)]
+const SVG_INCLUDES: &[&str] = &["./dist/static/**/*.svg"];
+
+/// Globs matched in addition to the includes with
+/// `CompressionOptions::include_fonts`
+#[cfg(engine)]
+const FONT_INCLUDES: &[&str] = &[
+    "./dist/static/**/*.ttf",
+    "./dist/static/**/*.otf",
+    "./dist/static/**/*.eot",
+];
+
+/// Callback for `CompressionOptions::on_progress`, called with a file's path,
+/// how many files have been processed so far and the total number of files
+pub type ProgressCallback = Box<dyn Fn(&std::path::Path, usize, usize) + Send + Sync>;
+
+/// Callback for `CompressionOptions::before_compress`, called with a source's
+/// path
+pub type BeforeCompressCallback = Box<dyn Fn(&std::path::Path) + Send + Sync>;
+
+/// Callback for `CompressionOptions::after_compress`, called with the path of
+/// a compressed file and its size compared to its source's
+pub type AfterCompressCallback = Box<dyn Fn(&std::path::Path, CompressionFileResult) + Send + Sync>;
 
 /// Options for the auto-compressor.
 ///
 /// # Defaults
 ///
 /// * `include`: `["./dist/static/**/*.css", "./dist/pkg/**/*.wasm", "./dist/pkg/**/*.js"]`
-/// * `exclude`: `[]`
-/// * `should_run`: `true`
+/// * `exclude`: `["./dist/**/*.map"]`
+/// * `include_html`: `true`
+/// * `include_svg`: `true`
+/// * `include_fonts`: `false`
+/// * `include_regex`: `[]`
+/// * `exclude_regex`: `[]`
+/// * `base_dir`: `None`
+/// * `fail_on_empty_glob`: `false`
+/// * `follow_symlinks`: `false`
+/// * `should_run`: `ShouldRun::Value(true)`
+/// * `algorithms`: all algorithms enabled via features, with default parameters
+/// * `threads`: `Threads::Auto`
+/// * `max_in_flight_bytes`: `None`
+/// * `level`: `CompressionLevel::Default`
+/// * `buffer_size`: `None`
+/// * `mmap_threshold`: `None`
+/// * `incremental`: `true`
+/// * `force`: `false`
+/// * `cache`: `None`
+/// * `preserve_mtime`: `false`
+/// * `min_size`: `1024`
+/// * `max_size`: `None`
+/// * `overrides`: `[]`
+/// * `algorithm_overrides`: `[]`
+/// * `large_file_threshold`: `None`
+/// * `large_file_level`: `CompressionLevel::Fastest`
+/// * `skip_if_larger`: `true`
+/// * `min_ratio`: `0.0`
+/// * `skip_pre_compressed`: `true`
+/// * `extra_skip_extensions`: `[]`
+/// * `on_error`: `ErrorPolicy::Abort`
+/// * `encoders`: `[]`
+/// * `custom_compressor`: `None`
+/// * `on_progress`: `None`
+/// * `before_compress`: `None`
+/// * `after_compress`: `None`
+/// * `log_level`: `log::Level::Info`
+/// * `write_report`: `false`
+/// * `manifest`: `None`
+/// * `generate_headers_file`: `None`
+/// * `dry_run`: `false`
+/// * `verify`: `false`
+/// * `prune_orphans`: `false`
+/// * `output_dir`: `None`
+/// * `output_root`: `None`
+/// * `copy_sources`: `true`
+/// * `naming`: `NamingScheme::Append`
+/// * `output_suffixes`: `[]`
+/// * `in_place`: `false`
+/// * `brotli_dictionary`: `None`
+/// * `zstd_train_dictionary`: `None`
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(
+        default = "CompressionOptions::converted_defaults",
+        deny_unknown_fields,
+        bound(
+            serialize = "M: serde::Serialize",
+            deserialize = "M: serde::Deserialize<'de> + From<&'static str>"
+        )
+    )
+)]
 pub struct CompressionOptions<M>
 where
     M: AsRef<str> + 'static + Send,
 {
-    /// Globs for included files
+    /// Globs for included files. Globs that can't be parsed, in any of the
+    /// options, fail the run with `CompressionError::InvalidGlob`.
+    ///
+    /// Files the plugin writes itself, like the `cache`, `manifest` and
+    /// report files, are never compressed, even when a glob like
+    /// `./dist/**/*.json` matches them.
     pub include: Vec<M>,
-    /// Globs for excluded files that are matched by the included glob
+    /// Globs for excluded files that are matched by the included glob. Source
+    /// maps are excluded by default, since they're large and rarely served
+    /// compressed. Replace the defaults to compress them anyway.
     pub exclude: Vec<M>,
+    /// Also compress the exported HTML pages and Perseus' JSON state files,
+    /// which usually shrink by more than 70%. Adds
+    /// `./dist/exported/**/*.html` and `./dist/**/*.json` to `include`, but
+    /// unlike those they don't warn when they don't match anything, since
+    /// there are no HTML pages until the app is exported.
+    pub include_html: bool,
+    /// Also compress SVG images in the static directory, which are XML and
+    /// usually shrink by 60-80%. Adds `./dist/static/**/*.svg` to `include`,
+    /// without warning if there are none, like `include_html`.
+    pub include_svg: bool,
+    /// Also compress TrueType, OpenType and EOT fonts in the static
+    /// directory, which shrink well and are still served as fallbacks for
+    /// browsers without WOFF2 support. Adds `./dist/static/**/*.ttf`,
+    /// `./dist/static/**/*.otf` and `./dist/static/**/*.eot` to `include`,
+    /// like `include_html`. WOFF and WOFF2 fonts are already compressed, so
+    /// they're left out and skipped by `skip_pre_compressed`.
+    pub include_fonts: bool,
+    /// Regular expressions that files matched by `include` must also match,
+    /// e.g. `[0-9a-f]{8}\.js$` for scripts with a content hash in their name.
+    /// They're matched against the whole path as it came out of the glob. If
+    /// empty, every file matched by `include` is kept. Requires the `regex`
+    /// feature.
+    ///
+    /// Expressions that can't be parsed fail the run with
+    /// `CompressionError::InvalidRegex`.
+    #[cfg(feature = "regex")]
+    pub include_regex: Vec<M>,
+    /// Regular expressions for excluded files, in addition to `exclude`.
+    /// Requires the `regex` feature.
+    #[cfg(feature = "regex")]
+    pub exclude_regex: Vec<M>,
+    /// The directory relative include, exclude and override globs are
+    /// resolved against. Perseus is normally run from the project root, but
+    /// if your build runs it from somewhere else, e.g. the root of a monorepo,
+    /// the default globs silently match nothing. Set this to the project
+    /// root to fix that. `None` resolves globs against the working directory.
+    pub base_dir: Option<std::path::PathBuf>,
+    /// Fail with `CompressionError::EmptyGlob` if an include glob doesn't
+    /// match any files once the excludes are applied, instead of just logging
+    /// a warning. A typo in a glob otherwise leaves files uncompressed without
+    /// failing the build, so this is useful for strict CI setups.
+    pub fail_on_empty_glob: bool,
+    /// Compress files that are symlinks. Their compressed files are written
+    /// next to the link, but with the link target's contents, which can be
+    /// confusing for shared assets linked into `dist`, so they're skipped and
+    /// logged by default.
+    pub follow_symlinks: bool,
     /// Should the plugin actually do anything?
     /// Set this via conditional compilation to disable compression in development
     /// but enable it in production.
@@ -72,11 +322,448 @@ where
     ///
     /// ```
     /// let options = perseus_compress::CompressionOptions {
-    ///     should_run: cfg!(not(debug_assertions)),
+    ///     should_run: cfg!(not(debug_assertions)).into(),
+    ///     ..perseus_compress::CompressionOptions::default()
+    /// };
+    /// ```
+    ///
+    /// To decide when the plugin runs instead, e.g. based on an environment
+    /// variable that's only set in CI, use `ShouldRun::lazy`:
+    ///
+    /// ```
+    /// use perseus_compress::{CompressionOptions, ShouldRun};
+    ///
+    /// let options = CompressionOptions {
+    ///     should_run: ShouldRun::lazy(|| std::env::var_os("CI").is_some()),
+    ///     ..CompressionOptions::default()
+    /// };
+    /// ```
+    pub should_run: ShouldRun,
+    /// Algorithms to compress with. Each file is compressed once per algorithm,
+    /// so enabling both `brotli` and `gzip` produces `.br` and `.gz` files
+    /// side by side.
+    ///
+    /// Algorithms can be picked at runtime, but each variant only exists if
+    /// its feature is enabled.
+    pub algorithms: Vec<Algorithm>,
+    /// How many threads files are compressed on. The number that was used
+    /// is reported in `CompressionReport::threads`. Requires the `parallel`
+    /// feature, otherwise files are compressed one at a time.
+    #[cfg(feature = "parallel")]
+    pub threads: Threads,
+    /// The most bytes of sources compressed at once, for CI runners with
+    /// little memory. Files wait for others to finish until they fit, so
+    /// small files still fill up the threads while a large one is being
+    /// compressed, and a file larger than the budget is compressed on its
+    /// own. `None` compresses as many files at once as there are threads.
+    /// A budget of 0 fails the run with `CompressionError::OutOfRange`.
+    /// Requires the `parallel` feature.
+    ///
+    /// Only the sources are counted, but the encoders need memory too,
+    /// mostly for brotli's window. Each brotli encoder uses a few times
+    /// `2^lgwin` bytes, which `BrotliParams::large_window_threshold` raises
+    /// to 16 MiB for large files, so lower those as well if a budget alone
+    /// doesn't keep the build from running out of memory.
+    #[cfg(feature = "parallel")]
+    pub max_in_flight_bytes: Option<u64>,
+    /// How hard the compressor should try. Trades build time for smaller files.
+    ///
+    /// The level never changes which files are produced, so it can be switched
+    /// per build without touching your server config.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use perseus_compress::{CompressionLevel, CompressionOptions};
+    ///
+    /// let options = CompressionOptions {
+    ///     level: if cfg!(debug_assertions) {
+    ///         CompressionLevel::Fastest
+    ///     } else {
+    ///         CompressionLevel::Default
+    ///     },
+    ///     ..CompressionOptions::default()
+    /// };
+    /// ```
+    pub level: CompressionLevel,
+    /// Size in bytes of the buffers the encoders write through. Brotli uses
+    /// it for its internal buffer and gzip and deflate for a buffered writer in
+    /// front of the encoder. Larger buffers can speed up compressing large WASM
+    /// files, especially on network file systems. `None` uses 64 KiB.
+    ///
+    /// Sizes of 0 or above 64 MiB fail the run with
+    /// `CompressionError::OutOfRange`.
+    pub buffer_size: Option<usize>,
+    /// Sources larger than this many bytes are memory-mapped instead of read
+    /// into memory, which saves a copy of large files like a WASM binary.
+    /// Sources that can't be mapped are read as usual. `None` never maps.
+    ///
+    /// A mapped source must not be truncated while it's compressed, e.g. by a
+    /// rebuild writing to `dist` at the same time, or the process crashes.
+    /// Requires the `mmap` feature.
+    #[cfg(feature = "mmap")]
+    pub mmap_threshold: Option<u64>,
+    /// Skip files whose compressed outputs already exist and are at least as
+    /// new as the source, or with `cache`, whose contents and settings haven't
+    /// changed since the last run. Skipped files are counted in
+    /// `CompressionReport::files_skipped_up_to_date`. Turn this off to
+    /// compress every file on every run, while still keeping compressed files
+    /// the plugin didn't write, unlike `force`.
+    pub incremental: bool,
+    /// Recompress every file, even if its compressed output is at least as new
+    /// as the source or `cache` says it's unchanged. Useful after changing the
+    /// compression settings or for clean deploy builds. Otherwise up to date
+    /// files are skipped and counted in
+    /// `CompressionReport::files_skipped_up_to_date`. Can be set with the
+    /// `PERSEUS_COMPRESS_FORCE` environment variable, see `from_env`.
+    ///
+    /// Also overwrites compressed files that weren't written by an earlier
+    /// run, see `cache`.
+    pub force: bool,
+    /// A file to keep a SHA-256 hash of every source and its compressed files
+    /// in, along with the settings it was compressed with, e.g.
+    /// `./dist/.perseus-compress-cache.json`. Sources whose contents and
+    /// settings haven't changed since the last run are skipped, even if their
+    /// mtimes did, like after a fresh checkout in CI, restoring `dist` from a
+    /// cache or a skewed clock in a container. Sources are compressed again
+    /// if one of their compressed files was changed or deleted since. Only
+    /// the hashes and settings decide, instead of the mtimes, so this replaces
+    /// the check `force` disables.
+    ///
+    /// Changing the algorithms, level, `skip_if_larger`, `min_ratio`, where
+    /// outputs are written or the plugin version invalidates the cache, and a
+    /// missing or corrupt cache file just means every file is compressed.
+    /// Custom encoders are only told apart by their suffix, so run with
+    /// `force` after changing one. Requires the `cache` feature.
+    ///
+    /// The cache also records which compressed files the plugin wrote. A
+    /// compressed file the last run didn't write, like a hand-optimized
+    /// `main.css.br` next to `main.css`, is kept with a warning instead of
+    /// being overwritten, unless `force` is set. Without a cache file from an
+    /// earlier run, every compressed file is treated as the plugin's own.
+    #[cfg(feature = "cache")]
+    pub cache: Option<std::path::PathBuf>,
+    /// Give compressed files the modification time of their source instead
+    /// of the time they were written, so tools further down the line, like
+    /// `rsync` or a CDN upload, can trust their timestamps. Compressed files
+    /// with the same mtime as their source still count as up to date.
+    pub preserve_mtime: bool,
+    /// Files smaller than this many bytes are not compressed. Tiny files can
+    /// end up larger after compression, so serving them compressed is
+    /// pointless.
+    pub min_size: u64,
+    /// Files larger than this many bytes are not compressed. Every file is
+    /// read into memory before compressing it, so this keeps huge files from
+    /// exhausting memory. `None` compresses files of any size.
+    pub max_size: Option<u64>,
+    /// Settings for specific files, as pairs of a glob and the settings for
+    /// the files it matches. The first matching glob wins and files that
+    /// don't match any keep the settings above. Besides the level and size
+    /// thresholds, this can pick different algorithms for some files.
+    ///
+    /// ```
+    /// use perseus_compress::{CompressionLevel, CompressionOptions, PerFileOptions};
+    ///
+    /// let options = CompressionOptions {
+    ///     overrides: vec![
+    ///         (
+    ///             "./dist/pkg/*.wasm",
+    ///             PerFileOptions {
+    ///                 level: Some(CompressionLevel::Best),
+    ///                 ..PerFileOptions::default()
+    ///             },
+    ///         ),
+    ///         (
+    ///             "./dist/static/**/*.js",
+    ///             PerFileOptions {
+    ///                 min_size: Some(4096),
+    ///                 ..PerFileOptions::default()
+    ///             },
+    ///         ),
+    ///     ],
+    ///     ..CompressionOptions::default()
+    /// };
+    /// ```
+    pub overrides: Vec<(M, PerFileOptions)>,
+    /// Algorithms for files with specific extensions, as pairs of an
+    /// extension without the leading dot and the algorithms to use instead
+    /// of `algorithms`. Matching ignores case and only looks at the last
+    /// extension, so `"js"` matches `main.min.js`. An override that sets
+    /// `PerFileOptions::algorithms` takes precedence.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "brotli", feature = "gzip"))] {
+    /// use perseus_compress::{Algorithm, CompressionOptions, GzipParams};
+    ///
+    /// let options = CompressionOptions {
+    ///     // Brotli for everything else
+    ///     algorithm_overrides: vec![("wasm", vec![Algorithm::Gzip(GzipParams::default())])],
     ///     ..CompressionOptions::default()
     /// };
+    /// # }
+    /// ```
+    pub algorithm_overrides: Vec<(M, Vec<Algorithm>)>,
+    /// Files larger than this many bytes are compressed at `large_file_level`
+    /// instead, even if an override sets their level. Keeps a huge WASM
+    /// binary from slowing down every build while small files still get the
+    /// best ratio. Downgraded files are logged and marked with
+    /// `FileReport::downgraded`. `None` never downgrades.
+    pub large_file_threshold: Option<u64>,
+    /// The level files larger than `large_file_threshold` are compressed at
+    pub large_file_level: CompressionLevel,
+    /// Don't write compressed files that are at least as large as their
+    /// source, e.g. for already compressed images matched by a broad glob.
+    /// Servers fall back to the source when there's no compressed file.
+    pub skip_if_larger: bool,
+    /// The fraction of the source's size a compressed file has to save to be
+    /// written, from `0.0` to `1.0`. For example `0.1` only keeps compressed
+    /// files that are at least 10% smaller than their source. `0.0` keeps
+    /// everything.
+    ///
+    /// Discarded files are never written, any left over from an earlier run
+    /// are deleted, and they're counted in
+    /// `CompressionReport::outputs_discarded`.
+    pub min_ratio: f32,
+    /// Skip files whose format is already compressed, like images, fonts and
+    /// archives, based on their extension. Compressing them again barely saves
+    /// anything, so this keeps a broad glob like `**/*` from wasting time.
+    pub skip_pre_compressed: bool,
+    /// Extensions to skip in addition to the built-in list of pre-compressed
+    /// formats, without the leading dot, e.g. `"bin"`. Only used with
+    /// `skip_pre_compressed`.
+    pub extra_skip_extensions: Vec<M>,
+    /// What to do when a single file fails to compress
+    pub on_error: ErrorPolicy,
+    /// Custom encoders, used in addition to `algorithms`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub encoders: Vec<Box<dyn Encoder + Send + Sync>>,
+    /// Compress with this instead of the built-in encoder of the first of
+    /// `algorithms`, e.g. to call an external program. Its outputs are named,
+    /// served and verified like that algorithm's, so it has to produce the
+    /// same format, and the other algorithms are compressed as usual. The
+    /// compression level doesn't apply to it.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub custom_compressor: Option<Box<dyn Compressor>>,
+    /// Called after each file is processed, e.g. to drive a progress bar. With
+    /// the `parallel` feature this is called from several threads, so files
+    /// may be reported out of order.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub on_progress: Option<ProgressCallback>,
+    /// Called before each matched source is processed, e.g. to log it to a
+    /// remote service, including sources that then turn out to be up to date
+    /// or too small. Like `on_progress`, this is called from several threads with
+    /// the `parallel` feature.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub before_compress: Option<BeforeCompressCallback>,
+    /// Called for every compressed file that's kept, with its size and its
+    /// source's, after its source has been compressed with all encoders.
+    /// Sources that are up to date or that didn't compress well enough don't
+    /// produce any. Like `on_progress`, this is called from several threads
+    /// with the `parallel` feature.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub after_compress: Option<AfterCompressCallback>,
+    /// The most verbose messages to log. Every file is logged at `Debug`, a
+    /// summary of each run at `Info` and files that failed or didn't get
+    /// smaller at `Warn`. Requires the `log` feature, which is enabled by
+    /// default.
+    #[cfg(feature = "log")]
+    pub log_level: log::Level,
+    /// Write a JSON report of every run to `./dist/compress-report.json`, in
+    /// `base_dir` if it's set.
+    /// Useful for tracking compression effectiveness in CI. Requires the
+    /// `json` feature.
+    #[cfg(feature = "json")]
+    pub write_report: bool,
+    /// Write a JSON manifest of every source's compressed files to this
+    /// path after each run, so a server can pick the right
+    /// `Content-Encoding` without checking the file system on every request.
+    /// Files that were up to date are listed too, but not sources without
+    /// any compressed files or that failed to compress. The manifest is
+    /// written to a temporary file and renamed into place, so a deploy step
+    /// reading it never sees a partially written one.
+    ///
+    /// The schema only changes along with `version`, so it can be
+    /// deserialized with serde. Paths are as they were matched by the
+    /// include globs, `ratio` is the compressed size divided by the
+    /// original's and `dictionary` is `FileReport::dictionary`. With
+    /// `NamingScheme::ContentHash`, `path` is the name with the hash:
+    ///
+    /// ```json
+    /// {
+    ///   "version": 1,
+    ///   "files": {
+    ///     "./dist/static/main.css": {
+    ///       "size": 4096,
+    ///       "variants": [
+    ///         {
+    ///           "encoding": "br",
+    ///           "path": "./dist/static/main.css.br",
+    ///           "size": 1024,
+    ///           "ratio": 0.25,
+    ///           "dictionary": false
+    ///         }
+    ///       ]
+    ///     }
+    ///   }
+    /// }
+    /// ```
+    ///
+    /// Requires the `json` feature.
+    #[cfg(feature = "json")]
+    pub manifest: Option<std::path::PathBuf>,
+    /// Write a Netlify `_headers` file to this path after each run, which
+    /// Cloudflare Pages reads as well. It has a rule for every compressed
+    /// file setting the `Content-Encoding` browsers need to decode it and
+    /// the `Content-Type` of its source, so e.g. `/main.css.br` is served as
+    /// compressed CSS. Put it in the published directory, usually
+    /// `./dist/exported/_headers`: URLs are the outputs' paths relative to
+    /// it, and outputs outside of it are left out. So are outputs that
+    /// browsers can't decode, like XZ or dictionary-compressed files, and
+    /// those of custom encoders without `Encoder::content_encoding`.
+    ///
+    /// The file is replaced on every run, so rules of your own can't go in
+    /// the same file.
+    pub generate_headers_file: Option<std::path::PathBuf>,
+    /// Compress every matched file in memory and report what would be
+    /// written, without writing, copying or deleting anything. Useful for
+    /// checking which files a glob picks up. The plugin prints a table of
+    /// the files to stdout, see `CompressionReport::table`.
+    pub dry_run: bool,
+    /// Decompress every compressed file in memory and check that its SHA-256
+    /// hash matches the source's, failing with
+    /// `CompressionError::VerificationFailed` if it doesn't. Outputs of custom
+    /// encoders that can't decode aren't checked. Requires the `verify`
+    /// feature.
+    #[cfg(feature = "verify")]
+    pub verify: bool,
+    /// After compressing, delete compressed files whose source no longer
+    /// exists, e.g. after a static file was renamed, so they can't be served
+    /// for a resource that's gone. Only files named like an output of the
+    /// configured encoders for a path matching an include are deleted, so
+    /// anything else in `dist` is left alone. A compressed file that's put
+    /// there on purpose, like a hand-made `legacy.css.gz`, can be kept by
+    /// matching it with an exclude. The count is reported in
+    /// `CompressionReport::files_pruned`.
+    pub prune_orphans: bool,
+    /// Write compressed files to this directory instead of next to their
+    /// sources. The sources are copied there too, so the directory can be
+    /// served on its own, unless `copy_sources` is off.
+    ///
+    /// Paths are kept relative to `output_root`, or by default to the
+    /// non-wildcard part of the include glob that matched, so
+    /// `./dist/static/**/*.css` puts `./dist/static/css/main.css` at
+    /// `<output_dir>/css/main.css` and `<output_dir>/css/main.css.br`.
+    pub output_dir: Option<std::path::PathBuf>,
+    /// The directory paths in `output_dir` are kept relative to, instead of
+    /// each include glob's non-wildcard part. With `./dist` as the root,
+    /// `./dist/static/css/main.css` is compressed to
+    /// `<output_dir>/static/css/main.css.br`. A matched file outside the root
+    /// fails the run with `CompressionError::OutsideOutputRoot` before
+    /// anything is written.
+    pub output_root: Option<std::path::PathBuf>,
+    /// Copy the sources into `output_dir` along with their compressed files.
+    /// Turn it off to get a tree with only the compressed files, e.g. to
+    /// upload them separately from the sources.
+    pub copy_sources: bool,
+    /// How compressed files are named, e.g. `main.css.br` or `main.br`.
+    /// Change this if your server expects a different convention.
+    pub naming: NamingScheme,
+    /// Suffixes to name compressed files with instead of the encoders' own,
+    /// as pairs of an encoder's suffix and the one to use, both without the
+    /// leading dot, e.g. `("br", "brotli")` for `main.css.brotli`. Other
+    /// encoders keep their suffix. With `skip_pre_compressed`, files with
+    /// these suffixes aren't compressed again.
+    pub output_suffixes: Vec<(M, M)>,
+    /// **Overwrite each source with its compressed bytes** instead of writing
+    /// a compressed file next to it, for servers that send every client the
+    /// same `Content-Encoding`. Clients that can't decode it can't use the
+    /// files at all, and the uncompressed files are gone afterwards, so only
+    /// use this on build output that's regenerated anyway.
+    ///
+    /// Only the first encoder that applies to a file is used, and `naming`
+    /// is ignored. Sources are replaced through a temporary file that's
+    /// renamed over them, so an interrupted build never leaves a partially
+    /// written one. Sources that the encoder can already decode are assumed
+    /// to have been compressed by an earlier run and are skipped, since their
+    /// modification times can't tell, unless `force` is set.
+    pub in_place: bool,
+    /// A custom dictionary to compress brotli files with. Pages that share a
+    /// lot of boilerplate compress much better against a dictionary built from
+    /// that boilerplate.
+    ///
+    /// Browsers can only decode these files if they were sent the same
+    /// dictionary, so they're marked with `FileReport::dictionary` and your
+    /// server has to take care of serving them only to clients that have it.
+    #[cfg(feature = "brotli")]
+    pub brotli_dictionary: Option<std::path::PathBuf>,
+    /// Train a zstd dictionary of at most this many bytes on all included
+    /// files and compress every `.zst` file against it. This works best for
+    /// lots of small, similar files, like translation bundles.
+    ///
+    /// The dictionary is written to `./dist/compress-dictionary.zstd`, in
+    /// `base_dir` if it's set, for the server to ship, and the outputs are marked with
+    /// `FileReport::dictionary`. If there are too few files or training fails,
+    /// files are compressed without a dictionary.
+    #[cfg(feature = "zstd")]
+    pub zstd_train_dictionary: Option<usize>,
+}
+
+impl CompressionOptions<&'static str> {
+    /// The default options with the algorithms and level from `profile`.
+    /// Other fields can still be set explicitly:
+    ///
+    /// ```
+    /// use perseus_compress::{CompressionOptions, CompressionProfile};
+    ///
+    /// let options = CompressionOptions {
+    ///     min_size: 512,
+    ///     ..CompressionOptions::with_profile(CompressionProfile::Smallest)
+    /// };
+    /// ```
+    pub fn with_profile(profile: CompressionProfile) -> Self {
+        Self {
+            algorithms: profile.algorithms(),
+            level: profile.level(),
+            ..Self::default()
+        }
+    }
+}
+
+impl CompressionOptions<String> {
+    /// The default options, but compressing every file under `root` with one
+    /// of `extensions` instead of the default includes, for when listing
+    /// globs is overkill. Extensions are given without the leading dot.
+    /// Other fields can still be set explicitly:
+    ///
+    /// ```
+    /// use perseus_compress::CompressionOptions;
+    ///
+    /// let options = CompressionOptions {
+    ///     min_size: 512,
+    ///     ..CompressionOptions::for_dir("./dist", &["css", "js", "wasm"])
+    /// };
     /// ```
-    pub should_run: bool,
+    pub fn for_dir(root: impl AsRef<std::path::Path>, extensions: &[&str]) -> Self {
+        let root = glob::Pattern::escape(&root.as_ref().to_string_lossy());
+        let include = extensions
+            .iter()
+            .map(|extension| {
+                let extension = glob::Pattern::escape(extension.trim_start_matches('.'));
+                std::path::Path::new(&root)
+                    .join("**")
+                    .join(format!("*.{}", extension))
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        Self {
+            include,
+            include_html: false,
+            include_svg: false,
+            include_fonts: false,
+            ..Self::converted_defaults()
+        }
+    }
 }
 
 impl Default for CompressionOptions<&'static str> {
@@ -87,126 +774,2970 @@ impl Default for CompressionOptions<&'static str> {
                 "./dist/pkg/**/*.wasm",
                 "./dist/pkg/**/*.js",
             ],
-            exclude: vec![],
-            should_run: true,
+            exclude: vec!["./dist/**/*.map"],
+            include_html: true,
+            include_svg: true,
+            include_fonts: false,
+            #[cfg(feature = "regex")]
+            include_regex: vec![],
+            #[cfg(feature = "regex")]
+            exclude_regex: vec![],
+            base_dir: None,
+            fail_on_empty_glob: false,
+            follow_symlinks: false,
+            should_run: ShouldRun::Value(true),
+            algorithms: Algorithm::enabled(),
+            #[cfg(feature = "parallel")]
+            threads: Threads::Auto,
+            #[cfg(feature = "parallel")]
+            max_in_flight_bytes: None,
+            level: CompressionLevel::Default,
+            buffer_size: None,
+            #[cfg(feature = "mmap")]
+            mmap_threshold: None,
+            incremental: true,
+            force: false,
+            #[cfg(feature = "cache")]
+            cache: None,
+            preserve_mtime: false,
+            min_size: 1024,
+            max_size: None,
+            overrides: vec![],
+            algorithm_overrides: vec![],
+            large_file_threshold: None,
+            large_file_level: CompressionLevel::Fastest,
+            skip_if_larger: true,
+            min_ratio: 0.0,
+            skip_pre_compressed: true,
+            extra_skip_extensions: vec![],
+            on_error: ErrorPolicy::Abort,
+            encoders: vec![],
+            custom_compressor: None,
+            on_progress: None,
+            before_compress: None,
+            after_compress: None,
+            #[cfg(feature = "log")]
+            log_level: log::Level::Info,
+            #[cfg(feature = "json")]
+            write_report: false,
+            #[cfg(feature = "json")]
+            manifest: None,
+            generate_headers_file: None,
+            dry_run: false,
+            #[cfg(feature = "verify")]
+            verify: false,
+            prune_orphans: false,
+            output_dir: None,
+            output_root: None,
+            copy_sources: true,
+            naming: NamingScheme::Append,
+            output_suffixes: vec![],
+            in_place: false,
+            #[cfg(feature = "brotli")]
+            brotli_dictionary: None,
+            #[cfg(feature = "zstd")]
+            zstd_train_dictionary: None,
         }
     }
 }
 
-/// Plugin constructor
-pub fn get_compression_plugin<M: AsRef<str> + Send + Sync>() -> Plugin<CompressionOptions<M>> {
-    #[allow(unused_mut)]
-    Plugin::new(
-        "perseus-compress",
-        |mut actions| {
-            #[cfg(engine)]
-            {
-                use perseus::plugins::PluginAction;
-                actions
-                    .build_actions
-                    .after_successful_build
-                    .register_plugin("perseus-compress", |_, data| {
-                        let options = data.downcast_ref::<CompressionOptions<M>>().unwrap();
-                        if options.should_run {
-                            compress_everything(options)
-                        } else {
-                            Ok(())
-                        }
-                    });
-                actions
-                    .export_actions
-                    .after_successful_export
-                    .register_plugin("perseus-compress", |_, data| {
-                        let options = data.downcast_ref::<CompressionOptions<M>>().unwrap();
-                        if options.should_run {
-                            compress_everything(options)
-                        } else {
-                            Ok(())
-                        }
-                    });
+impl<M> CompressionOptions<M>
+where
+    M: AsRef<str> + From<&'static str> + 'static + Send,
+{
+    /// The defaults with the globs converted to `M`, for
+    /// `CompressionOptions::builder` and options loaded from a file
+    pub(crate) fn converted_defaults() -> Self {
+        let defaults = CompressionOptions::<&'static str>::default();
+        Self {
+            include: defaults.include.into_iter().map(M::from).collect(),
+            exclude: defaults.exclude.into_iter().map(M::from).collect(),
+            include_html: defaults.include_html,
+            include_svg: defaults.include_svg,
+            include_fonts: defaults.include_fonts,
+            #[cfg(feature = "regex")]
+            include_regex: defaults.include_regex.into_iter().map(M::from).collect(),
+            #[cfg(feature = "regex")]
+            exclude_regex: defaults.exclude_regex.into_iter().map(M::from).collect(),
+            base_dir: defaults.base_dir,
+            fail_on_empty_glob: defaults.fail_on_empty_glob,
+            follow_symlinks: defaults.follow_symlinks,
+            should_run: defaults.should_run,
+            algorithms: defaults.algorithms,
+            #[cfg(feature = "parallel")]
+            threads: defaults.threads,
+            #[cfg(feature = "parallel")]
+            max_in_flight_bytes: defaults.max_in_flight_bytes,
+            level: defaults.level,
+            buffer_size: defaults.buffer_size,
+            #[cfg(feature = "mmap")]
+            mmap_threshold: defaults.mmap_threshold,
+            incremental: defaults.incremental,
+            force: defaults.force,
+            #[cfg(feature = "cache")]
+            cache: defaults.cache,
+            preserve_mtime: defaults.preserve_mtime,
+            min_size: defaults.min_size,
+            max_size: defaults.max_size,
+            overrides: defaults
+                .overrides
+                .into_iter()
+                .map(|(glob, file_options)| (M::from(glob), file_options))
+                .collect(),
+            algorithm_overrides: defaults
+                .algorithm_overrides
+                .into_iter()
+                .map(|(extension, algorithms)| (M::from(extension), algorithms))
+                .collect(),
+            large_file_threshold: defaults.large_file_threshold,
+            large_file_level: defaults.large_file_level,
+            skip_if_larger: defaults.skip_if_larger,
+            min_ratio: defaults.min_ratio,
+            skip_pre_compressed: defaults.skip_pre_compressed,
+            extra_skip_extensions: defaults
+                .extra_skip_extensions
+                .into_iter()
+                .map(M::from)
+                .collect(),
+            on_error: defaults.on_error,
+            encoders: defaults.encoders,
+            custom_compressor: defaults.custom_compressor,
+            on_progress: defaults.on_progress,
+            before_compress: defaults.before_compress,
+            after_compress: defaults.after_compress,
+            #[cfg(feature = "log")]
+            log_level: defaults.log_level,
+            #[cfg(feature = "json")]
+            write_report: defaults.write_report,
+            #[cfg(feature = "json")]
+            manifest: defaults.manifest,
+            generate_headers_file: defaults.generate_headers_file,
+            dry_run: defaults.dry_run,
+            #[cfg(feature = "verify")]
+            verify: defaults.verify,
+            prune_orphans: defaults.prune_orphans,
+            output_dir: defaults.output_dir,
+            output_root: defaults.output_root,
+            copy_sources: defaults.copy_sources,
+            naming: defaults.naming,
+            output_suffixes: defaults
+                .output_suffixes
+                .into_iter()
+                .map(|(suffix, output_suffix)| (M::from(suffix), M::from(output_suffix)))
+                .collect(),
+            in_place: defaults.in_place,
+            #[cfg(feature = "brotli")]
+            brotli_dictionary: defaults.brotli_dictionary,
+            #[cfg(feature = "zstd")]
+            zstd_train_dictionary: defaults.zstd_train_dictionary,
+        }
+    }
+}
+
+/// A compression algorithm and its parameters. Each variant requires its
+/// feature to be enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Algorithm {
+    /// Brotli, produces `.br` files. Requires the `brotli` feature.
+    #[cfg(feature = "brotli")]
+    Brotli(BrotliParams),
+    /// Gzip, produces `.gz` files. Requires the `gzip` feature.
+    #[cfg(feature = "gzip")]
+    Gzip(GzipParams),
+    /// Raw DEFLATE without the gzip wrapper, produces `.deflate` files.
+    /// Requires the `deflate` feature.
+    #[cfg(feature = "deflate")]
+    Deflate(DeflateParams),
+    /// Zstandard, produces `.zst` files. Requires the `zstd` feature.
+    #[cfg(feature = "zstd")]
+    Zstd(ZstdParams),
+    /// XZ, produces `.xz` files. Browsers can't decode this, so it's only
+    /// useful for archiving build output. Requires the `xz` feature.
+    #[cfg(feature = "xz")]
+    Xz(XzParams),
+}
+
+impl Algorithm {
+    /// All algorithms enabled via features, with default parameters
+    pub fn enabled() -> Vec<Algorithm> {
+        vec![
+            #[cfg(feature = "brotli")]
+            Algorithm::Brotli(BrotliParams::default()),
+            #[cfg(feature = "gzip")]
+            Algorithm::Gzip(GzipParams::default()),
+            #[cfg(feature = "deflate")]
+            Algorithm::Deflate(DeflateParams::default()),
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd(ZstdParams::default()),
+            #[cfg(feature = "xz")]
+            Algorithm::Xz(XzParams::default()),
+        ]
+    }
+
+    /// The extension appended to compressed files
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            #[cfg(feature = "brotli")]
+            Algorithm::Brotli(_) => "br",
+            #[cfg(feature = "gzip")]
+            Algorithm::Gzip(_) => "gz",
+            #[cfg(feature = "deflate")]
+            Algorithm::Deflate(_) => "deflate",
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd(_) => "zst",
+            #[cfg(feature = "xz")]
+            Algorithm::Xz(_) => "xz",
+        }
+    }
+
+    /// Compresses `input` into `output` with this algorithm at `level`, e.g.
+    /// from a pre-deploy script. Like `compress_file` with only this algorithm
+    /// and otherwise default options, so the output is the same as
+    /// `compress_paths` would write.
+    ///
+    /// ```no_run
+    /// use perseus_compress::{Algorithm, CompressionLevel};
+    /// use std::path::Path;
+    ///
+    /// for algorithm in Algorithm::enabled() {
+    ///     let output = format!("./dist/pkg/perseus_engine_bg.wasm.{}", algorithm.extension());
+    ///     algorithm.compress_file(
+    ///         Path::new("./dist/pkg/perseus_engine_bg.wasm"),
+    ///         Path::new(&output),
+    ///         CompressionLevel::Best,
+    ///     )?;
+    /// }
+    /// # Ok::<(), perseus_compress::CompressionError>(())
+    /// ```
+    #[cfg(engine)]
+    pub fn compress_file(
+        &self,
+        input: &Path,
+        output: &Path,
+        level: CompressionLevel,
+    ) -> Result<CompressionFileResult, CompressionError> {
+        let options = CompressionOptions {
+            algorithms: vec![*self],
+            level,
+            ..CompressionOptions::default()
+        };
+        crate::compress_file(input, output, &options)
+    }
+
+    /// Checks that the parameters and a custom `level` are within the range
+    /// the encoder accepts
+    #[cfg(engine)]
+    #[cfg_attr(
+        not(any(
+            feature = "brotli",
+            feature = "gzip",
+            feature = "deflate",
+            feature = "zstd",
+            feature = "xz"
+        )),
+        allow(unused_variables)
+    )]
+    pub(crate) fn validate(&self, level: CompressionLevel) -> Result<(), CompressionError> {
+        // A custom level replaces the one in the parameters
+        let custom = match level {
+            CompressionLevel::Custom(level) => Some(level),
+            _ => None,
+        };
+        match *self {
+            #[cfg(feature = "brotli")]
+            Algorithm::Brotli(params) => {
+                let quality = custom.unwrap_or(params.quality);
+                CompressionError::check_range("brotli quality", quality, 0, 11)?;
+                CompressionError::check_range("brotli window (lgwin)", params.lgwin, 10, 24)
             }
-            actions
-        },
-        empty_control_actions_registrar,
-        PluginEnv::Server,
-    )
+            #[cfg(feature = "gzip")]
+            Algorithm::Gzip(params) => {
+                CompressionError::check_range("gzip level", custom.unwrap_or(params.level), 0, 9)
+            }
+            #[cfg(feature = "deflate")]
+            Algorithm::Deflate(params) => {
+                let level = custom.unwrap_or(params.level);
+                CompressionError::check_range("deflate level", level, 0, 9)
+            }
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd(params) => {
+                let level = custom.map_or(i64::from(params.level), i64::from);
+                CompressionError::check_range("zstd level", level, 1, 22)
+            }
+            #[cfg(feature = "xz")]
+            Algorithm::Xz(params) => {
+                CompressionError::check_range("xz preset", custom.unwrap_or(params.preset), 0, 9)
+            }
+        }
+    }
 }
 
-#[cfg(engine)]
-fn compress_everything<M: AsRef<str> + Send>(
-    options: &CompressionOptions<M>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    use std::collections::HashSet;
-    use std::fs::File;
+/// Brotli encoder parameters
+///
+/// # Defaults
+///
+/// * `quality`: `11`
+/// * `lgwin`: `22`
+/// * `mode`: `BrotliMode::Auto`
+/// * `large_window_threshold`: `None`
+/// * `multithread_threshold`: `None`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct BrotliParams {
+    /// Compression quality, from 0 to 11. Higher is smaller but slower.
+    ///
+    /// The default of 11 can be very slow for large WASM binaries, so you may
+    /// want to lower this for development builds.
+    ///
+    /// Values outside this range fail the run with
+    /// `CompressionError::OutOfRange`.
+    pub quality: u32,
+    /// Base 2 logarithm of the sliding window size, from 10 to 24. Large WASM
+    /// binaries compress noticeably better with a bigger window.
+    ///
+    /// Values outside this range fail the run with
+    /// `CompressionError::OutOfRange`.
+    pub lgwin: u32,
+    /// What kind of data the encoder should tune for
+    pub mode: BrotliMode,
+    /// Files larger than this many bytes are compressed with the largest
+    /// window, regardless of `lgwin`, and the encoder is told their size up
+    /// front. Multi-megabyte WASM binaries repeat themselves over longer
+    /// distances than the default window covers, so this can save a lot of
+    /// transfer size without slowing down small files. `None` always uses
+    /// `lgwin`.
+    pub large_window_threshold: Option<u64>,
+    /// Files larger than this many bytes are split into parts that are
+    /// compressed on `CompressionOptions::threads` threads at once, which
+    /// helps when most of the build time is spent on one large WASM binary.
+    /// The output is still a standard brotli stream, but usually a little
+    /// larger, and the whole file is held in memory while it's compressed.
+    /// `None` compresses every file on a single thread, as does not enabling
+    /// the `parallel` feature.
+    pub multithread_threshold: Option<u64>,
+}
 
-    let excludes = options
-        .exclude
-        .iter()
-        .map(|item| glob::glob(item.as_ref()))
-        .filter_map(Result::ok)
-        .flatten()
-        .filter_map(Result::ok)
-        .collect::<HashSet<_>>();
-    let files = options
-        .include
-        .iter()
-        .map(|item| glob::glob(item.as_ref()))
-        .filter_map(Result::ok)
-        .flatten()
-        .filter_map(Result::ok)
-        .filter(|path| !excludes.contains(path));
+impl Default for BrotliParams {
+    fn default() -> Self {
+        Self {
+            quality: 11,
+            lgwin: 22,
+            mode: BrotliMode::Auto,
+            large_window_threshold: None,
+            multithread_threshold: None,
+        }
+    }
+}
+
+/// The kind of data brotli is tuned for. This only affects the compression
+/// ratio, the output decodes the same either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum BrotliMode {
+    /// Picks the mode from the source's extension: `Text` for HTML, CSS, JS,
+    /// JSON, SVG, XML and plain text, `Font` for `.woff2` and `Generic` for
+    /// everything else, like WASM
+    #[default]
+    Auto,
+    /// No assumptions about the data
+    Generic,
+    /// UTF-8 text, like CSS, JS and HTML
+    Text,
+    /// WOFF 2.0 fonts
+    Font,
+}
+
+/// Gzip encoder parameters. The gzip header never includes a timestamp or
+/// file name, so compressing the same source always produces the same file.
+///
+/// # Defaults
+///
+/// * `level`: `6`
+/// * `backend`: `GzipBackend::Flate2`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct GzipParams {
+    /// Compression level, from 0 to 9. Level 1 is roughly 10 times faster
+    /// than level 9 while only producing slightly larger files.
+    ///
+    /// Values outside this range fail the run with
+    /// `CompressionError::OutOfRange`.
+    pub level: u32,
+    /// The encoder used to produce the gzip stream
+    pub backend: GzipBackend,
+}
 
-    for file in files {
-        let mut original = File::open(&file)?;
-        let out_path = compressed_path(&file);
-        let mut out_file = File::create(out_path)?;
-        let mut compressed = compressor(&mut out_file);
-        std::io::copy(&mut original, &mut compressed)?;
+impl Default for GzipParams {
+    fn default() -> Self {
+        Self {
+            level: 6,
+            backend: GzipBackend::Flate2,
+        }
     }
-    Ok(())
 }
 
-#[cfg(all(engine, feature = "brotli"))]
-fn compressed_path(original_path: &Path) -> PathBuf {
-    let mut path = original_path.parent().unwrap().to_path_buf();
-    path.push(format!(
-        "{}.br",
-        original_path.file_name().unwrap().to_str().unwrap()
-    ));
-    path
+/// Encoder used to produce gzip files. Both produce standard gzip streams, so
+/// this doesn't affect how the files are served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum GzipBackend {
+    /// `flate2`, which is fast and honors the compression level
+    #[default]
+    Flate2,
+    /// Zopfli, which produces the smallest possible gzip files but is many
+    /// times slower. Ignores the compression level, except that
+    /// `CompressionLevel::Fastest` falls back to `flate2`. Requires the `zopfli`
+    /// feature.
+    #[cfg(feature = "zopfli")]
+    Zopfli,
 }
 
-#[cfg(all(engine, feature = "gzip"))]
-fn compressed_path(original_path: &Path) -> PathBuf {
-    let mut path = original_path.parent().unwrap().to_path_buf();
-    path.push(format!(
-        "{}.gz",
-        original_path.file_name().unwrap().to_str().unwrap()
-    ));
-    path
+/// Raw DEFLATE encoder parameters
+///
+/// # Defaults
+///
+/// * `level`: `6`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct DeflateParams {
+    /// Compression level, from 0 to 9
+    ///
+    /// Values outside this range fail the run with
+    /// `CompressionError::OutOfRange`.
+    pub level: u32,
 }
 
-#[cfg(all(engine, not(any(feature = "gzip", feature = "brotli"))))]
-fn compressed_path(_original_path: &Path) -> PathBuf {
-    unimplemented!(
-        "No compression algorithm set. Please use either the 'gzip' or 'brotli' feature."
-    );
+impl Default for DeflateParams {
+    fn default() -> Self {
+        Self { level: 6 }
+    }
 }
 
-#[cfg(all(engine, feature = "brotli"))]
-fn compressor(file: &mut impl Write) -> impl Write + '_ {
-    use brotli::enc::BrotliEncoderParams;
-    brotli::CompressorWriter::with_params(file, 4096, &BrotliEncoderParams::default())
+/// Zstandard encoder parameters
+///
+/// # Defaults
+///
+/// * `level`: `19`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct ZstdParams {
+    /// Compression level, from 1 to 22. Higher is smaller but slower.
+    ///
+    /// Values outside this range fail the run with
+    /// `CompressionError::OutOfRange`.
+    pub level: i32,
 }
 
-#[cfg(all(engine, feature = "gzip"))]
-fn compressor(file: &mut impl Write) -> impl Write + '_ {
-    flate2::write::GzEncoder::new(file, flate2::Compression::default())
+impl Default for ZstdParams {
+    fn default() -> Self {
+        Self { level: 19 }
+    }
 }
 
-#[cfg(all(engine, not(any(feature = "gzip", feature = "brotli"))))]
-fn compressor(_file: &mut impl Write) -> std::fs::File {
-    unimplemented!(
-        "No compression algorithm set. Please use either the 'gzip' or 'brotli' feature."
-    );
+/// XZ encoder parameters
+///
+/// # Defaults
+///
+/// * `preset`: `6`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct XzParams {
+    /// Compression preset, from 0 to 9
+    ///
+    /// Values outside this range fail the run with
+    /// `CompressionError::OutOfRange`.
+    pub preset: u32,
+}
+
+impl Default for XzParams {
+    fn default() -> Self {
+        Self { preset: 6 }
+    }
+}
+
+/// Compression level, shared between all algorithms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum CompressionLevel {
+    /// Fastest compression, largest files. Meant for development builds, so
+    /// gzip uses `flate2` even if `GzipBackend::Zopfli` is selected.
+    Fastest,
+    /// The level set in the algorithm's parameters, i.e. `BrotliParams::quality`
+    #[default]
+    Default,
+    /// Best compression, slowest
+    Best,
+    /// A custom level. For brotli this is the quality (0-11), for gzip and
+    /// deflate the compression level (0-9), for zstd the compression level
+    /// (1-22) and for xz the preset (0-9). Levels outside an enabled
+    /// algorithm's range fail the run with `CompressionError::OutOfRange`.
+    Custom(u32),
+}
+
+/// A preset for the algorithms' parameters and the compression level, for
+/// use with `CompressionOptions::with_profile`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum CompressionProfile {
+    /// The fastest builds, for `perseus serve`. Compresses at
+    /// `CompressionLevel::Fastest`.
+    Fastest,
+    /// The default parameters, which compress well in reasonable time
+    #[default]
+    Balanced,
+    /// The smallest files at the cost of much slower builds, for
+    /// `perseus deploy`. Uses every algorithm's highest level, the largest
+    /// brotli window and, with the `zopfli` feature, `GzipBackend::Zopfli`.
+    Smallest,
+}
+
+impl CompressionProfile {
+    /// The algorithms enabled via features, with this profile's parameters
+    pub fn algorithms(self) -> Vec<Algorithm> {
+        match self {
+            Self::Fastest | Self::Balanced => Algorithm::enabled(),
+            Self::Smallest => vec![
+                #[cfg(feature = "brotli")]
+                Algorithm::Brotli(BrotliParams {
+                    quality: 11,
+                    lgwin: 24,
+                    ..BrotliParams::default()
+                }),
+                #[cfg(feature = "gzip")]
+                Algorithm::Gzip(GzipParams {
+                    level: 9,
+                    #[cfg(feature = "zopfli")]
+                    backend: GzipBackend::Zopfli,
+                    #[cfg(not(feature = "zopfli"))]
+                    backend: GzipBackend::Flate2,
+                }),
+                #[cfg(feature = "deflate")]
+                Algorithm::Deflate(DeflateParams { level: 9 }),
+                #[cfg(feature = "zstd")]
+                Algorithm::Zstd(ZstdParams { level: 22 }),
+                #[cfg(feature = "xz")]
+                Algorithm::Xz(XzParams { preset: 9 }),
+            ],
+        }
+    }
+
+    /// The level files are compressed at with this profile
+    pub fn level(self) -> CompressionLevel {
+        match self {
+            Self::Fastest => CompressionLevel::Fastest,
+            Self::Balanced | Self::Smallest => CompressionLevel::Default,
+        }
+    }
+}
+
+/// How many threads to compress files on, see `CompressionOptions::threads`
+#[cfg(feature = "parallel")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Threads {
+    /// Rayon's global thread pool, which has one thread per core unless the
+    /// `RAYON_NUM_THREADS` environment variable or the application configured
+    /// it otherwise
+    #[default]
+    Auto,
+    /// A fixed number of threads, e.g. to leave cores free on a shared CI
+    /// runner. `Threads::Fixed(1)` compresses files one at a time on the
+    /// calling thread, as does `Threads::Fixed(0)`.
+    Fixed(usize),
+}
+
+/// Whether the plugin compresses anything, see
+/// `CompressionOptions::should_run`. Converts from a `bool`.
+pub enum ShouldRun {
+    /// Decided when the options are put together
+    Value(bool),
+    /// Decided by calling the closure each time the plugin runs, e.g. to
+    /// check an environment variable that's only set during the build
+    Lazy(Box<dyn Fn() -> bool + Send + Sync>),
+}
+
+impl ShouldRun {
+    /// Decides when the plugin runs by calling `should_run` then
+    pub fn lazy(should_run: impl Fn() -> bool + Send + Sync + 'static) -> Self {
+        Self::Lazy(Box::new(should_run))
+    }
+
+    /// Whether the plugin should run, calling the closure of `ShouldRun::Lazy`
+    pub fn evaluate(&self) -> bool {
+        match self {
+            Self::Value(should_run) => *should_run,
+            Self::Lazy(should_run) => should_run(),
+        }
+    }
+}
+
+impl Default for ShouldRun {
+    fn default() -> Self {
+        Self::Value(true)
+    }
+}
+
+impl From<bool> for ShouldRun {
+    fn from(should_run: bool) -> Self {
+        Self::Value(should_run)
+    }
+}
+
+impl std::fmt::Debug for ShouldRun {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Value(should_run) => f.debug_tuple("Value").field(should_run).finish(),
+            Self::Lazy(_) => f.write_str("Lazy(..)"),
+        }
+    }
+}
+
+/// Serialized as a `bool`, which a closure is evaluated for
+#[cfg(feature = "serde")]
+impl serde::Serialize for ShouldRun {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bool(self.evaluate())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for ShouldRun {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        bool::deserialize(deserializer).map(Self::Value)
+    }
+}
+
+/// What to do when a file fails to compress
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ErrorPolicy {
+    /// Fail the build. The remaining files are still compressed so all
+    /// failures can be reported at once.
+    #[default]
+    Abort,
+    /// Print a warning and carry on. The build only fails if every file
+    /// failed. Failures are listed in `CompressionReport::failures`.
+    SkipAndWarn,
+}
+
+/// How the path of a compressed file is derived from its source, shown for
+/// `main.css` compressed with brotli
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum NamingScheme {
+    /// Append the encoding's extension: `main.css.br`
+    #[default]
+    Append,
+    /// Replace the source's extension: `main.br`. Sources that only differ in
+    /// their extension, like `main.css` and `main.js`, end up with the same
+    /// compressed file, so make sure your includes don't match any.
+    ReplaceExt,
+    /// Append the encoding's extension and put the file in this directory,
+    /// relative to the source's directory: `br/main.css.br` for
+    /// `NamingScheme::Sibling("br".into())`
+    Sibling(std::path::PathBuf),
+    /// Put the encoding's extension before the source's: `main.br.css`, so
+    /// servers that pick the MIME type by extension still get it right.
+    /// Sources without an extension get it appended.
+    BeforeExt,
+    /// Put a short hash of the compressed file's contents before the
+    /// encoding's extension: `main.css.1a2b3c4d.br`. The name changes
+    /// whenever the contents do, so the files can be cached forever. Files
+    /// with an outdated hash are deleted when a new one is written. The
+    /// names can't be derived from the source's, so servers have to look
+    /// them up in `CompressionOptions::manifest`.
+    ContentHash,
+}
+
+/// Settings for the files matched by one of `CompressionOptions::overrides`.
+/// Anything left as `None` keeps the setting from `CompressionOptions`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct PerFileOptions {
+    /// Replaces `CompressionOptions::algorithms`, e.g. to compress WASM at a
+    /// higher brotli quality than everything else. Custom encoders are still
+    /// used for every file.
+    pub algorithms: Option<Vec<Algorithm>>,
+    /// Replaces `CompressionOptions::level`
+    pub level: Option<CompressionLevel>,
+    /// Replaces `CompressionOptions::min_size`
+    pub min_size: Option<u64>,
+    /// Replaces `CompressionOptions::max_size`
+    pub max_size: Option<u64>,
+}
+
+#[cfg(engine)]
+impl<M: AsRef<str> + Send> CompressionOptions<M> {
+    /// The settings for `file`, with the first matching override applied
+    fn file_options(&self, file: &Path) -> FileOptions<'_> {
+        const NO_OVERRIDE: &PerFileOptions = &PerFileOptions {
+            algorithms: None,
+            level: None,
+            min_size: None,
+            max_size: None,
+        };
+        let file_options = self
+            .overrides
+            .iter()
+            .find(|(glob, _)| {
+                glob::Pattern::new(&self.resolve_glob(glob.as_ref()))
+                    .is_ok_and(|pattern| glob_matches(&pattern, file))
+            })
+            .map_or(NO_OVERRIDE, |(_, file_options)| file_options);
+        let extension = file
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+        let extension_algorithms = self
+            .algorithm_overrides
+            .iter()
+            .find(|(other, _)| {
+                other
+                    .as_ref()
+                    .trim_start_matches('.')
+                    .eq_ignore_ascii_case(extension)
+            })
+            .map(|(_, algorithms)| algorithms.as_slice());
+        FileOptions {
+            algorithms: file_options
+                .algorithms
+                .as_deref()
+                .or(extension_algorithms)
+                .unwrap_or(&self.algorithms),
+            level: file_options.level.unwrap_or(self.level),
+            min_size: file_options.min_size.unwrap_or(self.min_size),
+            max_size: file_options.max_size.or(self.max_size),
+        }
+    }
+
+    /// The suffix outputs of an encoder with `suffix` are named with, see
+    /// `CompressionOptions::output_suffixes`
+    fn output_suffix<'a>(&'a self, suffix: &'a str) -> &'a str {
+        self.output_suffixes
+            .iter()
+            .find(|(other, _)| other.as_ref().trim_start_matches('.') == suffix)
+            .map_or(suffix, |(_, output_suffix)| {
+                output_suffix.as_ref().trim_start_matches('.')
+            })
+    }
+
+    /// Where `encoder` writes the output for a source that's written to
+    /// `output`, without its hash for `NamingScheme::ContentHash`
+    fn compressed_path(&self, output: &Path, encoder: &dyn Encoder) -> PathBuf {
+        compressed_path(output, &self.naming, self.output_suffix(encoder.suffix()))
+    }
+
+    /// `buffer_size`, or the default if it's unset
+    fn checked_buffer_size(&self) -> Result<usize, CompressionError> {
+        let buffer_size = self.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE);
+        CompressionError::check_range(
+            "buffer size",
+            i64::try_from(buffer_size).unwrap_or(i64::MAX),
+            1,
+            MAX_BUFFER_SIZE as i64,
+        )?;
+        Ok(buffer_size)
+    }
+
+    /// `max_in_flight_bytes`, which can't be 0
+    #[cfg(feature = "parallel")]
+    fn checked_max_in_flight_bytes(&self) -> Result<Option<u64>, CompressionError> {
+        if let Some(max_in_flight_bytes) = self.max_in_flight_bytes {
+            CompressionError::check_range(
+                "max in-flight bytes",
+                i64::try_from(max_in_flight_bytes).unwrap_or(i64::MAX),
+                1,
+                i64::MAX,
+            )?;
+        }
+        Ok(self.max_in_flight_bytes)
+    }
+
+    /// `include`, followed by `HTML_INCLUDES`, `SVG_INCLUDES` and
+    /// `FONT_INCLUDES` if they're enabled
+    fn includes(&self) -> impl Iterator<Item = &str> {
+        let defaults = [
+            (self.include_html, HTML_INCLUDES),
+            (self.include_svg, SVG_INCLUDES),
+            (self.include_fonts, FONT_INCLUDES),
+        ];
+        let defaults = defaults
+            .into_iter()
+            .filter(|(enabled, _)| *enabled)
+            .flat_map(|(_, globs)| globs.iter().copied());
+        self.include.iter().map(AsRef::as_ref).chain(defaults)
+    }
+
+    /// The files the plugin writes, which are never compressed
+    fn own_files(&self) -> std::collections::HashSet<PathBuf> {
+        #[cfg(feature = "cache")]
+        let cache = self.cache.as_deref();
+        #[cfg(not(feature = "cache"))]
+        let cache = None;
+        #[cfg(feature = "zstd")]
+        let zstd_dictionary = Some(self.in_base_dir(encoder::ZSTD_DICTIONARY_PATH));
+        #[cfg(not(feature = "zstd"))]
+        let zstd_dictionary: Option<PathBuf> = None;
+        #[cfg(feature = "json")]
+        let (report, manifest) = (
+            Some(self.in_base_dir(REPORT_PATH)),
+            self.manifest.as_deref(),
+        );
+        #[cfg(not(feature = "json"))]
+        let (report, manifest): (Option<PathBuf>, _) = (None, None);
+        [
+            report.as_deref(),
+            cache,
+            manifest,
+            self.generate_headers_file.as_deref(),
+            zstd_dictionary.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .map(normalize_path)
+        .collect()
+    }
+
+    /// Checks that every include, exclude and override glob parses, since
+    /// `glob` would otherwise silently skip the ones that don't
+    fn check_globs(&self) -> Result<(), CompressionError> {
+        let globs = self
+            .include
+            .iter()
+            .chain(&self.exclude)
+            .chain(self.overrides.iter().map(|(glob, _)| glob));
+        for glob in globs {
+            glob::Pattern::new(&self.resolve_glob(glob.as_ref())).map_err(|source| {
+                CompressionError::InvalidGlob {
+                    pattern: glob.as_ref().to_string(),
+                    source,
+                }
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Compiles `include_regex` and `exclude_regex`
+    #[cfg(feature = "regex")]
+    fn regexes(&self) -> Result<(Vec<regex::Regex>, Vec<regex::Regex>), CompressionError> {
+        let compile = |patterns: &[M]| {
+            patterns
+                .iter()
+                .map(|pattern| {
+                    regex::Regex::new(pattern.as_ref()).map_err(|source| {
+                        CompressionError::InvalidRegex {
+                            pattern: pattern.as_ref().to_string(),
+                            source,
+                        }
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok((compile(&self.include_regex)?, compile(&self.exclude_regex)?))
+    }
+
+    /// `path`, one of the files the plugin writes to a fixed place in `dist`,
+    /// in `base_dir` if it's set
+    #[cfg(any(feature = "json", feature = "zstd"))]
+    pub(crate) fn in_base_dir(&self, path: &str) -> PathBuf {
+        match &self.base_dir {
+            Some(base_dir) => base_dir.join(trim_cur_dir(path)),
+            None => PathBuf::from(path),
+        }
+    }
+
+    /// `glob` with `base_dir` prepended if it's relative, without a leading
+    /// `./`. Glob matches never start with one, so a pattern that does would
+    /// never match them.
+    fn resolve_glob(&self, glob: &str) -> String {
+        let glob = trim_cur_dir(glob);
+        match &self.base_dir {
+            Some(base_dir) if Path::new(glob).is_relative() => {
+                let base_dir = glob::Pattern::escape(&base_dir.to_string_lossy());
+                let glob = Path::new(&base_dir).join(glob);
+                trim_cur_dir(&glob.to_string_lossy()).to_string()
+            }
+            _ => glob.to_string(),
+        }
+    }
+
+    /// Every algorithm files may be compressed with, without duplicates
+    fn all_algorithms(&self) -> Vec<Algorithm> {
+        let overrides = self
+            .overrides
+            .iter()
+            .filter_map(|(_, file_options)| file_options.algorithms.as_ref())
+            .chain(
+                self.algorithm_overrides
+                    .iter()
+                    .map(|(_, algorithms)| algorithms),
+            )
+            .flatten();
+        let mut algorithms = Vec::new();
+        for algorithm in self.algorithms.iter().chain(overrides) {
+            if !algorithms.contains(algorithm) {
+                algorithms.push(*algorithm);
+            }
+        }
+        algorithms
+    }
+
+    /// Every level files may be compressed at
+    fn levels(&self) -> impl Iterator<Item = CompressionLevel> + '_ {
+        let large_file_level = self.large_file_threshold.map(|_| self.large_file_level);
+        std::iter::once(self.level).chain(large_file_level).chain(
+            self.overrides
+                .iter()
+                .filter_map(|(_, file_options)| file_options.level),
+        )
+    }
+}
+
+/// The settings for a single file, after applying overrides
+#[cfg(engine)]
+struct FileOptions<'a> {
+    algorithms: &'a [Algorithm],
+    level: CompressionLevel,
+    min_size: u64,
+    max_size: Option<u64>,
+}
+
+/// Plugin constructor
+pub fn get_compression_plugin<M: AsRef<str> + Send + Sync>() -> Plugin<CompressionOptions<M>> {
+    #[allow(unused_mut)]
+    Plugin::new(
+        "perseus-compress",
+        |mut actions| {
+            #[cfg(engine)]
+            {
+                use perseus::plugins::PluginAction;
+                actions
+                    .build_actions
+                    .before_build
+                    .register_plugin("perseus-compress", |_, data| check_plugin::<M>(data));
+                actions
+                    .export_actions
+                    .before_export
+                    .register_plugin("perseus-compress", |_, data| check_plugin::<M>(data));
+                actions
+                    .build_actions
+                    .after_successful_build
+                    .register_plugin("perseus-compress", |_, data| run_plugin::<M>(data));
+                actions
+                    .export_actions
+                    .after_successful_export
+                    .register_plugin("perseus-compress", |_, data| run_plugin::<M>(data));
+            }
+            actions
+        },
+        empty_control_actions_registrar,
+        PluginEnv::Server,
+    )
+}
+
+/// Catches options that can't compress anything before the build starts,
+/// rather than after it finished
+#[cfg(engine)]
+fn check_plugin<M: AsRef<str> + Send + Sync + 'static>(
+    data: &(dyn std::any::Any + Send + Sync),
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let options = data.downcast_ref::<CompressionOptions<M>>().unwrap();
+    if options.all_algorithms().is_empty()
+        && options.encoders.is_empty()
+        && options.should_run.evaluate()
+    {
+        return Err(CompressionError::NoAlgorithmEnabled.into());
+    }
+    Ok(())
+}
+
+#[cfg(engine)]
+fn run_plugin<M: AsRef<str> + Send + Sync + 'static>(
+    data: &(dyn std::any::Any + Send + Sync),
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let options = data.downcast_ref::<CompressionOptions<M>>().unwrap();
+    if options.should_run.evaluate() {
+        let report = compress_paths(options)?;
+        if options.dry_run {
+            println!("{}", report.table());
+        }
+        #[cfg(feature = "json")]
+        if options.write_report && !options.dry_run {
+            let path = options.in_base_dir(REPORT_PATH);
+            serde_json::to_vec_pretty(&report.to_json())
+                .map_err(std::io::Error::from)
+                .and_then(|json| write_file(&path, &json))
+                .map_err(CompressionError::io(path))?;
+        }
+    }
+    Ok(())
+}
+
+/// Compresses every file matched by the options and returns a report of what
+/// was written. This is what the plugin runs after each build, but it can
+/// also be called directly, e.g. from a deploy script.
+#[cfg(engine)]
+pub fn compress_paths<M: AsRef<str> + Send + Sync>(
+    options: &CompressionOptions<M>,
+) -> Result<CompressionReport, CompressionError> {
+    use std::collections::HashSet;
+
+    // Catch invalid parameters before anything is written
+    options.check_globs()?;
+    #[cfg(feature = "regex")]
+    let (include_regex, exclude_regex) = options.regexes()?;
+    #[cfg(feature = "parallel")]
+    let max_in_flight_bytes = options.checked_max_in_flight_bytes()?;
+    for algorithm in options.all_algorithms() {
+        for level in options.levels() {
+            algorithm.validate(level)?;
+        }
+    }
+
+    let start = std::time::Instant::now();
+    let excludes = options
+        .exclude
+        .iter()
+        .map(|item| glob::glob(&options.resolve_glob(item.as_ref())))
+        .filter_map(Result::ok)
+        .flatten()
+        .collect::<Result<HashSet<_>, _>>()?;
+    // E.g. the cache file, which `./dist/**/*.json` matches
+    let own_files = options.own_files();
+    let mut files = Vec::new();
+    // Includes can overlap, e.g. `./dist/**/*` and the HTML includes
+    let mut seen = HashSet::new();
+    for (index, item) in options.includes().enumerate() {
+        let Ok(paths) = glob::glob(&options.resolve_glob(item)) else {
+            continue;
+        };
+        let mut matched = 0;
+        let mut excluded = 0;
+        for path in paths {
+            let path = path?;
+            // Globs like `**/*` match directories too
+            if !path.is_file() {
+                continue;
+            }
+            if !options.follow_symlinks && path.is_symlink() {
+                info!(options, "skipping {}: it's a symlink", path.display());
+                continue;
+            }
+            if own_files.contains(&normalize_path(&path)) {
+                debug!(options, "skipping {}: the plugin wrote it", path.display());
+                continue;
+            }
+            #[cfg(feature = "regex")]
+            let excluded_by_regex = {
+                let path = path.to_string_lossy();
+                let matches = |regex: &regex::Regex| regex.is_match(&path);
+                (!include_regex.is_empty() && !include_regex.iter().any(matches))
+                    || exclude_regex.iter().any(matches)
+            };
+            #[cfg(not(feature = "regex"))]
+            let excluded_by_regex = false;
+            if excludes.contains(&path) || excluded_by_regex {
+                excluded += 1;
+                continue;
+            }
+            matched += 1;
+            if seen.insert(path.clone()) {
+                files.push(path);
+            }
+        }
+        // Usually a typo, which would otherwise silently leave files
+        // uncompressed. The HTML, SVG and font includes are allowed to be
+        // empty, there's no HTML until the app is exported.
+        if matched == 0 && index < options.include.len() {
+            if options.fail_on_empty_glob {
+                return Err(CompressionError::EmptyGlob {
+                    pattern: item.to_string(),
+                });
+            }
+            if excluded > 0 {
+                warn!(
+                    options,
+                    "include glob \"{}\" only matched excluded files", item
+                );
+            } else {
+                let relative_to = match &options.base_dir {
+                    Some(base_dir) => base_dir.display().to_string(),
+                    None => "the working directory".to_string(),
+                };
+                warn!(
+                    options,
+                    "include glob \"{}\" didn't match any files, check that it's right and relative to {}",
+                    item,
+                    relative_to
+                );
+            }
+        }
+    }
+    let (files, pre_compressed) = if options.skip_pre_compressed {
+        let output_suffixes = options
+            .output_suffixes
+            .iter()
+            .map(|(_, output_suffix)| output_suffix.as_ref());
+        let skip_extensions = PRE_COMPRESSED_EXTENSIONS
+            .iter()
+            .copied()
+            .chain(options.extra_skip_extensions.iter().map(AsRef::as_ref))
+            .chain(output_suffixes)
+            .map(|extension| extension.trim_start_matches('.').to_lowercase())
+            .collect::<HashSet<_>>();
+        let skipped = |path: &Path| {
+            path.extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| skip_extensions.contains(&extension.to_lowercase()))
+        };
+        files.into_iter().partition(|file| {
+            // Outputs of `NamingScheme::BeforeExt` keep the source's extension
+            let before_ext = options.naming == NamingScheme::BeforeExt
+                && file
+                    .file_stem()
+                    .is_some_and(|stem| skipped(Path::new(stem)));
+            !skipped(file) && !before_ext
+        })
+    } else {
+        (files, Vec::new())
+    };
+    info!(
+        options,
+        "{} file(s) matched, {} skipped as already compressed formats",
+        files.len() + pre_compressed.len(),
+        pre_compressed.len()
+    );
+    for file in &pre_compressed {
+        debug!(
+            options,
+            "skipping {}: already compressed format",
+            file.display()
+        );
+    }
+
+    if let (Some(_), Some(root)) = (&options.output_dir, &options.output_root) {
+        let outside = files
+            .iter()
+            .chain(&pre_compressed)
+            .find(|file| relative_to(root, file).is_none());
+        if let Some(file) = outside {
+            return Err(CompressionError::OutsideOutputRoot {
+                path: file.clone(),
+                root: root.clone(),
+            });
+        }
+    }
+
+    let encoders = encoder::load_encoders(options, &files)?;
+    if encoders.is_empty() {
+        return Err(CompressionError::NoAlgorithmEnabled);
+    }
+    #[cfg(feature = "cache")]
+    let cache = options
+        .cache
+        .as_deref()
+        .map(|path| cache::Cache::load(path, &encoders, options));
+
+    let roots = include_roots(options);
+    let output_path = |file: &Path| output_path_for(options, &roots, file);
+    if options.output_dir.is_some() && options.copy_sources && !options.dry_run {
+        for file in &pre_compressed {
+            copy_source(file, &output_path(file))?;
+        }
+    }
+
+    let total = files.len();
+    let processed = std::sync::atomic::AtomicUsize::new(0);
+    #[cfg(feature = "parallel")]
+    let budget = max_in_flight_bytes.map(Budget::new);
+    let compress = |file: &PathBuf| {
+        #[cfg(feature = "parallel")]
+        let _reserved = budget.as_ref().map(|budget| {
+            let size = std::fs::metadata(file).map_or(0, |metadata| metadata.len());
+            budget.reserve(size)
+        });
+        if let Some(before_compress) = &options.before_compress {
+            before_compress(file);
+        }
+        let result = compress_source(
+            file,
+            &output_path(file),
+            &encoders,
+            options,
+            #[cfg(feature = "cache")]
+            cache.as_ref(),
+        )
+        .map_err(|err| (file.clone(), err));
+        if let (Some(after_compress), Ok(SourceOutcome::Compressed { files, .. })) =
+            (&options.after_compress, &result)
+        {
+            for compressed in files {
+                let sizes = CompressionFileResult {
+                    original_size: compressed.original_size,
+                    compressed_size: compressed.compressed_size,
+                };
+                after_compress(&compressed.output, sizes);
+            }
+        }
+        if let Some(on_progress) = &options.on_progress {
+            let processed = processed.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+            on_progress(file, processed, total);
+        }
+        result
+    };
+
+    #[cfg(feature = "parallel")]
+    let (results, threads) = {
+        use rayon::prelude::*;
+
+        let compress_all = || files.par_iter().map(compress).collect::<Vec<_>>();
+        match options.threads {
+            // Not worth starting a thread pool for
+            Threads::Fixed(0 | 1) => (files.iter().map(compress).collect(), 1),
+            _ if files.len() <= 1 => (files.iter().map(compress).collect(), 1),
+            Threads::Fixed(threads) => {
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .map_err(CompressionError::ThreadPool)?;
+                (pool.install(compress_all), threads)
+            }
+            Threads::Auto => (compress_all(), rayon::current_num_threads()),
+        }
+    };
+    #[cfg(not(feature = "parallel"))]
+    let (results, threads) = (files.iter().map(compress).collect::<Vec<_>>(), 1);
+
+    let attempted = results.len();
+    let mut report = CompressionReport {
+        files_skipped: pre_compressed.len(),
+        threads,
+        ..CompressionReport::default()
+    };
+    for result in results {
+        match result {
+            Ok(outcome) => report.add_source(outcome),
+            Err(failure) => report.failures.push(failure),
+        }
+    }
+    #[cfg(feature = "cache")]
+    if let Some(cache) = cache {
+        if !options.dry_run {
+            cache.save()?;
+        }
+    }
+    #[cfg(feature = "json")]
+    if let (Some(manifest), false) = (&options.manifest, options.dry_run) {
+        let compressed = files
+            .iter()
+            .map(PathBuf::as_path)
+            .filter(|file| !report.failures.iter().any(|(failed, _)| failed == file));
+        manifest::write_manifest(manifest, compressed, &encoders, options, output_path)?;
+    }
+    if let (Some(headers), false) = (&options.generate_headers_file, options.dry_run) {
+        let compressed = files
+            .iter()
+            .map(PathBuf::as_path)
+            .filter(|file| !report.failures.iter().any(|(failed, _)| failed == file));
+        headers::write_headers(headers, compressed, &encoders, options, output_path)?;
+    }
+    if options.prune_orphans {
+        report.files_pruned = prune_orphans(options, &encoders, &roots, &excludes, output_path)?;
+    }
+    report.duration = start.elapsed();
+    if options.dry_run {
+        info!(
+            options,
+            "dry run, nothing was written: {}, would save {} bytes",
+            report,
+            report.bytes_saved()
+        );
+    } else {
+        info!(options, "{}, saved {} bytes", report, report.bytes_saved());
+    }
+
+    if report.failures.is_empty() {
+        return Ok(report);
+    }
+    match options.on_error {
+        ErrorPolicy::SkipAndWarn if report.failures.len() < attempted => {
+            for (path, err) in &report.failures {
+                warn!(options, "failed to compress {}: {}", path.display(), err);
+            }
+            Ok(report)
+        }
+        _ => Err(CompressionError::FilesFailed(report.failures)),
+    }
+}
+
+/// The leading directories of a glob, up to the first wildcard
+#[cfg(engine)]
+fn glob_root(pattern: &str) -> PathBuf {
+    Path::new(pattern)
+        .components()
+        .take_while(|component| {
+            !component
+                .as_os_str()
+                .to_string_lossy()
+                .contains(['*', '?', '['])
+        })
+        .collect()
+}
+
+/// Every include with its `glob_root`, the non-wildcard part outputs are
+/// placed relative to when writing to `output_dir`
+#[cfg(engine)]
+fn include_roots<M: AsRef<str> + Send>(
+    options: &CompressionOptions<M>,
+) -> Vec<(glob::Pattern, PathBuf)> {
+    options
+        .includes()
+        .filter_map(|item| glob::Pattern::new(&options.resolve_glob(item)).ok())
+        .map(|pattern| {
+            let root = glob_root(pattern.as_str());
+            (pattern, root)
+        })
+        .collect()
+}
+
+/// Where the source `file` is written to, which is only somewhere else with
+/// `output_dir`. `roots` are the `include_roots`.
+#[cfg(engine)]
+fn output_path_for<M: AsRef<str> + Send>(
+    options: &CompressionOptions<M>,
+    roots: &[(glob::Pattern, PathBuf)],
+    file: &Path,
+) -> PathBuf {
+    let Some(output_dir) = &options.output_dir else {
+        return file.to_path_buf();
+    };
+    let relative = match &options.output_root {
+        Some(root) => relative_to(root, file),
+        None => roots
+            .iter()
+            .find(|(pattern, _)| glob_matches(pattern, file))
+            .and_then(|(_, root)| relative_to(root, file)),
+    };
+    let relative = relative
+        .or_else(|| file.file_name().map(PathBuf::from))
+        .unwrap_or_else(|| file.to_path_buf());
+    output_dir.join(relative)
+}
+
+/// Whether the compressed file `existing` of `source` was put there by hand
+/// instead of being written by an earlier run, going by `cache`. Without a
+/// cache from an earlier run there's no telling, so nothing counts as hand
+/// written.
+#[cfg(engine)]
+fn hand_written(
+    source: &Path,
+    existing: &Path,
+    #[cfg(feature = "cache")] cache: Option<&cache::Cache>,
+) -> bool {
+    #[cfg(feature = "cache")]
+    if let Some(cache) = cache {
+        return cache.wrote(source, existing) == Some(false);
+    }
+    let _ = (source, existing);
+    false
+}
+
+/// `file`'s path relative to `root`, or `None` if it's outside it. Glob
+/// matches don't keep a leading `./`, so both are compared without them.
+#[cfg(engine)]
+pub(crate) fn relative_to(root: &Path, file: &Path) -> Option<PathBuf> {
+    normalize_path(file)
+        .strip_prefix(normalize_path(root))
+        .ok()
+        .map(Path::to_path_buf)
+}
+
+/// `path` without any `.` components, the way glob matches are returned
+#[cfg(engine)]
+pub(crate) fn normalize_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != std::path::Component::CurDir)
+        .collect()
+}
+
+/// `glob` without any leading `./`
+#[cfg(engine)]
+fn trim_cur_dir(mut glob: &str) -> &str {
+    while let Some(rest) = glob.strip_prefix("./") {
+        glob = rest.trim_start_matches('/');
+    }
+    glob
+}
+
+/// Whether `pattern`, which `resolve_glob` made, matches `path`, even if
+/// `path` was given with a leading `./`
+#[cfg(engine)]
+fn glob_matches(pattern: &glob::Pattern, path: &Path) -> bool {
+    pattern.matches_path(&normalize_path(path))
+}
+
+/// Deletes compressed files where the includes' outputs go that aren't the
+/// output of any source matched by the includes, returning how many there
+/// were. `roots` are the includes with their `glob_root`s, and files in
+/// `excludes` are never deleted.
+#[cfg(engine)]
+fn prune_orphans<M: AsRef<str> + Send>(
+    options: &CompressionOptions<M>,
+    encoders: &[LoadedEncoder],
+    roots: &[(glob::Pattern, PathBuf)],
+    excludes: &std::collections::HashSet<PathBuf>,
+    output_path: impl Fn(&Path) -> PathBuf,
+) -> Result<usize, CompressionError> {
+    use std::collections::{BTreeSet, HashSet};
+
+    // Every output of a source that still exists, including excluded ones
+    let expected = roots
+        .iter()
+        .filter_map(|(pattern, _)| glob::glob(pattern.as_str()).ok())
+        .flatten()
+        .filter_map(Result::ok)
+        .flat_map(|source| {
+            let output = output_path(&source);
+            encoders
+                .iter()
+                .map(move |loaded| options.compressed_path(&output, &*loaded.encoder))
+                .map(|compressed| normalize_path(&compressed))
+        })
+        .collect::<HashSet<_>>();
+
+    let mut orphans = BTreeSet::new();
+    for (pattern, root) in roots {
+        // Where the outputs of files matched by this include end up
+        let outputs = match &options.output_dir {
+            Some(output_dir) => {
+                let pattern = Path::new(pattern.as_str());
+                let relative = match &options.output_root {
+                    Some(output_root) => relative_to(output_root, pattern),
+                    None => pattern.strip_prefix(root).ok().map(Path::to_path_buf),
+                };
+                output_dir.join(relative.as_deref().unwrap_or(pattern))
+            }
+            None => PathBuf::from(pattern.as_str()),
+        };
+        for loaded in encoders {
+            let compressed = options.compressed_path(&outputs, &*loaded.encoder);
+            let mut globs = vec![match options.naming {
+                NamingScheme::ContentHash => {
+                    with_content_hash(&compressed, &"?".repeat(CONTENT_HASH_LEN))
+                }
+                _ => compressed,
+            }];
+            // A glob without an extension, like `*`, also matches sources with
+            // one, whose outputs have it after the encoding's
+            if let (NamingScheme::BeforeExt, None, Some(file_name)) =
+                (&options.naming, outputs.extension(), outputs.file_name())
+            {
+                let suffix = options.output_suffix(loaded.encoder.suffix());
+                let file_name = format!("{}.{}.*", file_name.to_string_lossy(), suffix);
+                globs.push(outputs.with_file_name(file_name));
+            }
+            let paths = globs
+                .iter()
+                .filter_map(|glob| glob::glob(&glob.to_string_lossy()).ok())
+                .flatten();
+            for compressed in paths {
+                let compressed = compressed?;
+                if excludes.contains(&compressed) {
+                    continue;
+                }
+                // The path the output would have without its hash
+                let unhashed = match options.naming {
+                    NamingScheme::ContentHash => without_content_hash(&compressed),
+                    _ => Some(compressed.clone()),
+                };
+                if unhashed.is_some_and(|unhashed| !expected.contains(&normalize_path(&unhashed))) {
+                    orphans.insert(compressed);
+                }
+            }
+        }
+    }
+
+    for orphan in &orphans {
+        if options.dry_run {
+            info!(
+                options,
+                "would prune {}: its source was deleted",
+                orphan.display()
+            );
+        } else {
+            info!(
+                options,
+                "pruning {}: its source was deleted",
+                orphan.display()
+            );
+            std::fs::remove_file(orphan).map_err(CompressionError::io(orphan))?;
+        }
+    }
+    Ok(orphans.len())
+}
+
+/// Suffixes of the built-in algorithms, including ones whose feature isn't
+/// enabled, so `clean_compressed_files` also removes outputs of an algorithm
+/// that's been switched off since
+#[cfg(engine)]
+const BUILT_IN_SUFFIXES: &[&str] = &["br", "gz", "deflate", "zst", "xz"];
+
+/// Deletes the compressed files of every source the options' includes match,
+/// returning how many there were, e.g. after switching from gzip to brotli
+/// so no stale `.gz` files are left behind. Outputs of every built-in
+/// algorithm are removed, whether it's enabled or not, along with those of
+/// `encoders`, and `output_suffixes`, `naming` and `output_dir` are taken
+/// into account.
+///
+/// Compressed files matched by an exclude are kept, and so are ones the last
+/// run didn't write according to `cache`, as they are when compressing. With
+/// `dry_run`, the rest are only logged and counted. With `in_place`, the
+/// outputs are the sources, so nothing is deleted.
+///
+/// Perseus has no clean action to hook into, so call this from a build
+/// script or wherever you clear out `dist`.
+///
+/// # Example
+///
+/// ```no_run
+/// use perseus_compress::{clean_compressed_files, CompressionOptions};
+///
+/// let removed = clean_compressed_files(&CompressionOptions::default())?;
+/// println!("removed {} compressed file(s)", removed);
+/// # Ok::<(), perseus_compress::CompressionError>(())
+/// ```
+#[cfg(engine)]
+pub fn clean_compressed_files<M: AsRef<str> + Send>(
+    options: &CompressionOptions<M>,
+) -> Result<usize, CompressionError> {
+    use std::collections::{BTreeSet, HashSet};
+
+    options.check_globs()?;
+    if options.in_place {
+        return Ok(0);
+    }
+    let excludes = options
+        .exclude
+        .iter()
+        .map(|item| glob::glob(&options.resolve_glob(item.as_ref())))
+        .filter_map(Result::ok)
+        .flatten()
+        .collect::<Result<HashSet<_>, _>>()?;
+    let suffixes = BUILT_IN_SUFFIXES
+        .iter()
+        .copied()
+        .chain(options.encoders.iter().map(|encoder| encoder.suffix()))
+        .map(|suffix| options.output_suffix(suffix))
+        .collect::<BTreeSet<_>>();
+    let roots = include_roots(options);
+    #[cfg(feature = "cache")]
+    let cache = options
+        .cache
+        .as_deref()
+        .map(|path| cache::Cache::load(path, &[], options));
+
+    let mut removed = BTreeSet::new();
+    for (pattern, _) in &roots {
+        let Ok(paths) = glob::glob(pattern.as_str()) else {
+            continue;
+        };
+        for source in paths {
+            let source = source?;
+            if !source.is_file() {
+                continue;
+            }
+            let output = output_path_for(options, &roots, &source);
+            for suffix in &suffixes {
+                let compressed = compressed_path(&output, &options.naming, suffix);
+                for existing in existing_outputs(&compressed, &options.naming) {
+                    let kept = excludes.contains(&normalize_path(&existing))
+                        || hand_written(
+                            &source,
+                            &existing,
+                            #[cfg(feature = "cache")]
+                            cache.as_ref(),
+                        );
+                    if !kept {
+                        removed.insert(existing);
+                    }
+                }
+            }
+        }
+    }
+
+    for compressed in &removed {
+        if options.dry_run {
+            info!(options, "would remove {}", compressed.display());
+        } else {
+            info!(options, "removing {}", compressed.display());
+            std::fs::remove_file(compressed).map_err(CompressionError::io(compressed))?;
+        }
+    }
+    Ok(removed.len())
+}
+
+/// Compresses every file under `root` with one of `extensions` with the
+/// default options, see `CompressionOptions::for_dir`.
+///
+/// # Example
+///
+/// ```no_run
+/// use perseus_compress::compress_dir;
+/// use std::path::Path;
+///
+/// let report = compress_dir(Path::new("./dist"), &["css", "js", "wasm", "html"])?;
+/// println!("{}", report);
+/// # Ok::<(), perseus_compress::CompressionError>(())
+/// ```
+#[cfg(engine)]
+pub fn compress_dir(
+    root: &Path,
+    extensions: &[&str],
+) -> Result<CompressionReport, CompressionError> {
+    compress_paths(&CompressionOptions::for_dir(root, extensions))
+}
+
+/// Compresses `input` into `output` with the first of the options' encoders
+/// that applies to it, e.g. from a build script. Unlike `compress_paths`, the
+/// file is always compressed and written, regardless of its size, whether `output` is up to
+/// date or how much space it saves. With `dry_run`, nothing is written.
+///
+/// To compress with a single algorithm without setting up options, use
+/// `Algorithm::compress_file`.
+///
+/// # Example
+///
+/// ```no_run
+/// use perseus_compress::{compress_file, CompressionOptions};
+/// use std::path::Path;
+///
+/// let result = compress_file(
+///     Path::new("./dist/pkg/perseus_engine_bg.wasm"),
+///     Path::new("./dist/pkg/perseus_engine_bg.wasm.br"),
+///     &CompressionOptions::default(),
+/// )?;
+/// println!("saved {} bytes", result.original_size - result.compressed_size);
+/// # Ok::<(), perseus_compress::CompressionError>(())
+/// ```
+#[cfg(engine)]
+pub fn compress_file<M: AsRef<str> + Send + Sync>(
+    input: &Path,
+    output: &Path,
+    options: &CompressionOptions<M>,
+) -> Result<CompressionFileResult, CompressionError> {
+    options.check_globs()?;
+    let file_options = options.file_options(input);
+    let level = file_options.level;
+    for algorithm in file_options.algorithms {
+        algorithm.validate(level)?;
+    }
+    let encoders = encoder::load_encoders(options, &[input.to_path_buf()])?;
+    let loaded = encoders
+        .iter()
+        .find(|loaded| loaded.used_for(file_options.algorithms))
+        .ok_or(CompressionError::NoAlgorithmEnabled)?;
+
+    let metadata = std::fs::metadata(input).map_err(CompressionError::io(input))?;
+    let original = read_source(input, metadata.len(), options)?;
+    let compressed = loaded.compress(input, &original, level, output)?;
+    #[cfg(feature = "verify")]
+    if options.verify {
+        let source_hash = <sha2::Sha256 as sha2::Digest>::digest(&original);
+        verify(output, &source_hash, &compressed, loaded.encoder_for(input))?;
+    }
+    if !options.dry_run {
+        let mtime_of = options.preserve_mtime.then_some(&metadata);
+        write_compressed(output, &compressed, false, mtime_of)?;
+    }
+    Ok(CompressionFileResult {
+        original_size: original.len() as u64,
+        compressed_size: compressed.len() as u64,
+    })
+}
+
+/// Limits the total size of the sources compressed at once, see
+/// `CompressionOptions::max_in_flight_bytes`
+#[cfg(all(engine, feature = "parallel"))]
+struct Budget {
+    limit: u64,
+    in_flight: std::sync::Mutex<u64>,
+    released: std::sync::Condvar,
+}
+
+#[cfg(all(engine, feature = "parallel"))]
+impl Budget {
+    fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            in_flight: std::sync::Mutex::new(0),
+            released: std::sync::Condvar::new(),
+        }
+    }
+
+    /// Blocks until a source of `size` bytes fits in the budget. Sources
+    /// larger than the budget wait until nothing else is being compressed.
+    fn reserve(&self, size: u64) -> Reservation<'_> {
+        let size = size.min(self.limit);
+        let mut in_flight = self.in_flight.lock().unwrap();
+        while *in_flight > 0 && *in_flight + size > self.limit {
+            in_flight = self.released.wait(in_flight).unwrap();
+        }
+        *in_flight += size;
+        Reservation { budget: self, size }
+    }
+}
+
+/// Part of a `Budget`, given back when dropped
+#[cfg(all(engine, feature = "parallel"))]
+struct Reservation<'a> {
+    budget: &'a Budget,
+    size: u64,
+}
+
+#[cfg(all(engine, feature = "parallel"))]
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        *self.budget.in_flight.lock().unwrap() -= self.size;
+        self.budget.released.notify_all();
+    }
+}
+
+/// Writes a compressed file, creating its directory if it doesn't exist yet
+#[cfg(engine)]
+fn write_output(output: &Path, compressed: &[u8]) -> Result<(), CompressionError> {
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent).map_err(CompressionError::io(parent))?;
+    }
+    write_file(output, compressed).map_err(CompressionError::io(output))
+}
+
+/// Replaces the file at `path` with `contents` by writing them to a temporary
+/// file next to it and renaming that, even without the `tempfile` feature.
+/// The file keeps its permissions.
+#[cfg(engine)]
+fn replace_file(path: &Path, contents: &[u8]) -> Result<(), CompressionError> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.tmp", file_name));
+    write_output(&temp, contents)?;
+    if let Ok(metadata) = std::fs::metadata(path) {
+        std::fs::set_permissions(&temp, metadata.permissions())
+            .map_err(CompressionError::io(&temp))?;
+    }
+    std::fs::rename(&temp, path).map_err(CompressionError::io(path))
+}
+
+/// Extensions of images, fonts, media and archives, which are already
+/// compressed. Skipped with `CompressionOptions::skip_pre_compressed`.
+/// Includes the outputs of every algorithm so a broad glob doesn't compress
+/// compressed files again.
+#[cfg(engine)]
+const PRE_COMPRESSED_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "gif", "webp", "avif", "heic", "heif", "jxl", "woff", "woff2", "mp3",
+    "mp4", "m4a", "m4v", "aac", "ogg", "oga", "ogv", "opus", "webm", "mov", "flac", "zip", "gz",
+    "tgz", "br", "zst", "xz", "bz2", "7z", "rar", "lz4", "deflate",
+];
+
+#[cfg(engine)]
+fn compress_source<M: AsRef<str> + Send>(
+    file: &Path,
+    output: &Path,
+    encoders: &[LoadedEncoder],
+    options: &CompressionOptions<M>,
+    #[cfg(feature = "cache")] cache: Option<&cache::Cache>,
+) -> Result<SourceOutcome, CompressionError> {
+    if options.output_dir.is_some() && options.copy_sources && !options.dry_run {
+        copy_source(file, output)?;
+    }
+
+    let file_options = options.file_options(file);
+    let metadata = std::fs::metadata(file).map_err(CompressionError::io(file))?;
+    let original_size = metadata.len();
+    // Say which threshold it was, so a missing compressed file is explained
+    let threshold = match file_options.max_size {
+        _ if original_size < file_options.min_size => {
+            Some(("below min_size", file_options.min_size))
+        }
+        Some(max_size) if original_size > max_size => Some(("above max_size", max_size)),
+        _ => None,
+    };
+    if let Some((relation, threshold)) = threshold {
+        debug!(
+            options,
+            "skipping {}: {} bytes is {} ({} bytes)",
+            file.display(),
+            original_size,
+            relation,
+            threshold
+        );
+        return Ok(SourceOutcome::SizeThreshold);
+    }
+
+    let downgraded = options
+        .large_file_threshold
+        .is_some_and(|threshold| original_size > threshold);
+    let level = if downgraded {
+        options.large_file_level
+    } else {
+        file_options.level
+    };
+
+    let mut outputs = encoders
+        .iter()
+        .filter(|loaded| loaded.used_for(file_options.algorithms))
+        .map(|loaded| {
+            let out_path = if options.in_place {
+                output.to_path_buf()
+            } else {
+                options.compressed_path(output, &*loaded.encoder)
+            };
+            (loaded, out_path)
+        })
+        .collect::<Vec<_>>();
+    // Only one encoder can replace the source
+    if options.in_place {
+        outputs.truncate(1);
+    }
+    // A compressed file the last run didn't write is one the user put there,
+    // so it isn't overwritten
+    if !options.force && !options.in_place {
+        outputs.retain(|(_, out_path)| {
+            let kept = existing_outputs(out_path, &options.naming)
+                .into_iter()
+                .find(|existing| {
+                    hand_written(
+                        file,
+                        existing,
+                        #[cfg(feature = "cache")]
+                        cache,
+                    )
+                });
+            if let Some(existing) = &kept {
+                warn!(
+                    options,
+                    "not compressing {} to {}: the last run didn't write it, so it isn't \
+                     overwritten unless force is set",
+                    file.display(),
+                    existing.display()
+                );
+            }
+            kept.is_none()
+        });
+    }
+    let skip_up_to_date = options.incremental && !options.force;
+    // With a cache, the source has to be read to tell whether it changed, and
+    // its mtime doesn't matter
+    #[cfg_attr(not(feature = "cache"), allow(unused_mut))]
+    let mut original = None;
+    #[cfg(feature = "cache")]
+    let mut cache_key = None;
+    #[cfg(feature = "cache")]
+    if let Some(cache) = cache {
+        let contents = read_source(file, original_size, options)?;
+        let outputs = outputs
+            .iter()
+            .map(|(loaded, out_path)| (*loaded, out_path.as_path()));
+        let key = cache.key(&contents, level, outputs, options);
+        if skip_up_to_date && cache.reuse(file, &key) {
+            debug!(
+                options,
+                "skipping {}: unchanged since the last run",
+                file.display()
+            );
+            return Ok(SourceOutcome::UpToDate);
+        }
+        original = Some(contents);
+        cache_key = Some(key);
+    }
+    // The output is the source itself, so its mtime never says anything
+    if skip_up_to_date && original.is_none() && !options.in_place {
+        outputs.retain(|(loaded, out_path)| {
+            let existing = existing_outputs(out_path, &options.naming);
+            !existing.iter().any(|existing| {
+                let inputs = std::iter::once(file).chain(loaded.dictionary.as_deref());
+                is_up_to_date(existing, inputs)
+            })
+        });
+    }
+    if outputs.is_empty() {
+        debug!(options, "skipping {}: already up to date", file.display());
+        return Ok(SourceOutcome::UpToDate);
+    }
+
+    if downgraded {
+        info!(
+            options,
+            "compressing {} at {:?}: {} bytes is above the large file threshold",
+            file.display(),
+            options.large_file_level,
+            original_size
+        );
+    }
+
+    // Read the source once and reuse it for every encoder
+    let original = match original {
+        Some(original) => original,
+        None => read_source(file, original_size, options)?,
+    };
+    let skip_compressed = options.in_place && !options.force;
+    if let (true, Some((loaded, _))) = (skip_compressed, outputs.first()) {
+        if let Some(Ok(_)) = loaded.encoder.decode(&original) {
+            debug!(
+                options,
+                "skipping {}: it was already compressed in place",
+                file.display()
+            );
+            return Ok(SourceOutcome::UpToDate);
+        }
+    }
+    #[cfg(feature = "verify")]
+    let source_hash = options
+        .verify
+        .then(|| <sha2::Sha256 as sha2::Digest>::digest(&original));
+    let mut reports = Vec::with_capacity(outputs.len());
+    #[cfg(feature = "cache")]
+    let mut written = Vec::new();
+    let mut discarded = 0;
+    for (loaded, out_path) in outputs {
+        let encoder = loaded.encoder_for(file);
+        // Compress into memory first so outputs that didn't save enough space
+        // are never written
+        let compressed = loaded.compress(file, &original, level, &out_path)?;
+        let output = match options.naming {
+            NamingScheme::ContentHash if !options.in_place => {
+                with_content_hash(&out_path, &content_hash(&compressed))
+            }
+            _ => out_path.clone(),
+        };
+        let report = FileReport {
+            source: file.to_path_buf(),
+            output,
+            encoding: encoder.suffix().to_string(),
+            original_size,
+            compressed_size: compressed.len() as u64,
+            dictionary: encoder.requires_dictionary(),
+            downgraded,
+        };
+        #[cfg(feature = "verify")]
+        if let Some(source_hash) = &source_hash {
+            verify(&report.output, source_hash, &compressed, encoder)?;
+        }
+        let larger = report.compressed_size >= original_size;
+        if (options.skip_if_larger && larger) || 1.0 - report.ratio() < options.min_ratio as f64 {
+            debug!(
+                options,
+                "discarding {}: only {:.1}% of the source was saved",
+                report.output.display(),
+                (1.0 - report.ratio()) * 100.0
+            );
+            // Don't leave behind an output from a previous run that would be
+            // served instead of the source
+            if !options.dry_run && !options.in_place {
+                remove_outputs(&out_path, &options.naming, None)?;
+            }
+            discarded += 1;
+            continue;
+        }
+        debug!(
+            options,
+            "{} {} to {} ({} -> {} bytes)",
+            if options.dry_run {
+                "would compress"
+            } else {
+                "compressed"
+            },
+            report.source.display(),
+            report.output.display(),
+            report.original_size,
+            report.compressed_size
+        );
+        if !options.dry_run {
+            let mtime_of = options.preserve_mtime.then_some(&metadata);
+            write_compressed(&report.output, &compressed, options.in_place, mtime_of)?;
+            if !options.in_place {
+                // Outputs with the hash of an earlier version of the source
+                remove_outputs(&out_path, &options.naming, Some(&report.output))?;
+            }
+        }
+        #[cfg(feature = "cache")]
+        if cache_key.is_some() {
+            written.push((report.output.clone(), cache::hash(&compressed)));
+        }
+        reports.push(report);
+    }
+    #[cfg(feature = "cache")]
+    if let (Some(cache), Some(key)) = (cache, &cache_key) {
+        cache.record(file, key, written);
+    }
+    if reports.is_empty() {
+        warn!(
+            options,
+            "skipping {}: compressing it didn't save enough space",
+            file.display()
+        );
+        return Ok(SourceOutcome::PoorRatio { discarded });
+    }
+    Ok(SourceOutcome::Compressed {
+        files: reports,
+        discarded,
+    })
+}
+
+/// Checks that `compressed` decompresses to a source with the hash `expected`
+#[cfg(all(engine, feature = "verify"))]
+fn verify(
+    output: &Path,
+    expected: &[u8],
+    compressed: &[u8],
+    encoder: &dyn Encoder,
+) -> Result<(), CompressionError> {
+    use sha2::{Digest, Sha256};
+
+    let Some(decompressed) = encoder.decode(compressed) else {
+        return Ok(());
+    };
+    let decompressed = decompressed.map_err(CompressionError::io(output))?;
+    let got = Sha256::digest(decompressed);
+    if expected != got.as_slice() {
+        return Err(CompressionError::VerificationFailed {
+            path: output.to_path_buf(),
+            expected: hex(expected),
+            got: hex(&got),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(all(engine, any(feature = "verify", feature = "cache")))]
+fn hex(hash: &[u8]) -> String {
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Writes `contents` to `path`. With the `tempfile` feature, they're written
+/// to a temporary file in the same directory first, which is then renamed, so
+/// an interrupted build never leaves a partially written file behind.
+#[cfg(engine)]
+fn write_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    #[cfg(feature = "tempfile")]
+    {
+        use std::io::Write;
+
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let mut file = tempfile::NamedTempFile::new_in(dir)?;
+        file.write_all(contents)?;
+        file.persist(path).map_err(|err| err.error)?;
+        Ok(())
+    }
+    #[cfg(not(feature = "tempfile"))]
+    std::fs::write(path, contents)
+}
+
+/// The contents of a source, see `CompressionOptions::mmap_threshold`
+#[cfg(engine)]
+enum Source {
+    Read(Vec<u8>),
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap),
+}
+
+#[cfg(engine)]
+impl std::ops::Deref for Source {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Read(contents) => contents,
+            #[cfg(feature = "mmap")]
+            Self::Mapped(map) => map,
+        }
+    }
+}
+
+#[cfg(engine)]
+impl AsRef<[u8]> for Source {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+/// Reads a source of `size` bytes, or maps it into memory if it's larger
+/// than `CompressionOptions::mmap_threshold`. Shared by `compress_file` and
+/// `compress_paths`, so both compress the same bytes.
+#[cfg(engine)]
+#[cfg_attr(not(feature = "mmap"), allow(unused_variables))]
+fn read_source<M: AsRef<str> + Send>(
+    file: &Path,
+    size: u64,
+    options: &CompressionOptions<M>,
+) -> Result<Source, CompressionError> {
+    // Empty files are never above the threshold, and can't be mapped on every
+    // platform anyway
+    #[cfg(feature = "mmap")]
+    if options
+        .mmap_threshold
+        .is_some_and(|threshold| size > threshold)
+    {
+        // SAFETY: the map is only read while the source is compressed, and
+        // `mmap_threshold` documents that it mustn't be truncated meanwhile
+        let map = std::fs::File::open(file).and_then(|file| unsafe { memmap2::Mmap::map(&file) });
+        match map {
+            Ok(map) => return Ok(Source::Mapped(map)),
+            Err(err) => debug!(
+                options,
+                "reading {} instead of mapping it: {}",
+                file.display(),
+                err
+            ),
+        }
+    }
+    std::fs::read(file)
+        .map(Source::Read)
+        .map_err(CompressionError::io(file))
+}
+
+/// Writes a compressed file, replacing the source through a temporary file
+/// with `in_place`. With `mtime_of`, the file gets the modification time of
+/// its source, see `CompressionOptions::preserve_mtime`. Shared by
+/// `compress_file` and `compress_paths`, so both write files the same way.
+#[cfg(engine)]
+fn write_compressed(
+    output: &Path,
+    compressed: &[u8],
+    in_place: bool,
+    mtime_of: Option<&std::fs::Metadata>,
+) -> Result<(), CompressionError> {
+    if in_place {
+        replace_file(output, compressed)?;
+    } else {
+        // Sibling directories, and without copied sources the directories in
+        // `output_dir`, don't exist until now
+        write_output(output, compressed)?;
+    }
+    if let Some(metadata) = mtime_of {
+        metadata
+            .modified()
+            .and_then(|modified| set_modified(output, modified))
+            .map_err(CompressionError::io(output))?;
+    }
+    Ok(())
+}
+
+/// Sets the modification time of the file at `path`, for
+/// `CompressionOptions::preserve_mtime`
+#[cfg(engine)]
+fn set_modified(path: &Path, modified: std::time::SystemTime) -> std::io::Result<()> {
+    std::fs::File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(modified)
+}
+
+/// Copies a source into `CompressionOptions::output_dir` so the uncompressed
+/// file can be served from there too
+#[cfg(engine)]
+fn copy_source(file: &Path, copy: &Path) -> Result<(), CompressionError> {
+    if is_up_to_date(copy, [file]) {
+        return Ok(());
+    }
+    if let Some(parent) = copy.parent() {
+        std::fs::create_dir_all(parent).map_err(CompressionError::io(parent))?;
+    }
+    std::fs::copy(file, copy).map_err(CompressionError::io(copy))?;
+    Ok(())
+}
+
+/// Whether the compressed file exists and is at least as new as its source
+/// and any dictionary it was compressed against. If a timestamp is unavailable
+/// the file is always recompressed.
+#[cfg(engine)]
+fn is_up_to_date<'a>(compressed: &Path, inputs: impl IntoIterator<Item = &'a Path>) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|meta| meta.modified());
+    let Ok(compressed) = modified(compressed) else {
+        return false;
+    };
+    // Equal timestamps count as up to date, since coarse mtime resolution
+    // often gives a source and its output the same timestamp
+    inputs
+        .into_iter()
+        .all(|input| matches!(modified(input), Ok(input) if compressed >= input))
+}
+
+/// The path of the compressed file for `original_path`, which is the source
+/// itself or its copy in `CompressionOptions::output_dir`
+#[cfg(engine)]
+fn compressed_path(original_path: &Path, naming: &NamingScheme, suffix: &str) -> PathBuf {
+    let parent = original_path.parent().unwrap();
+    let file_name = original_path.file_name().unwrap().to_str().unwrap();
+    match naming {
+        NamingScheme::Append | NamingScheme::ContentHash => {
+            parent.join(format!("{}.{}", file_name, suffix))
+        }
+        NamingScheme::ReplaceExt => original_path.with_extension(suffix),
+        NamingScheme::BeforeExt => match original_path.extension() {
+            Some(extension) => {
+                original_path.with_extension(format!("{}.{}", suffix, extension.to_str().unwrap()))
+            }
+            None => parent.join(format!("{}.{}", file_name, suffix)),
+        },
+        NamingScheme::Sibling(dir) => parent.join(dir).join(format!("{}.{}", file_name, suffix)),
+    }
+}
+
+/// Hex digits in the hash `NamingScheme::ContentHash` puts in file names
+#[cfg(engine)]
+const CONTENT_HASH_LEN: usize = 8;
+
+/// A short hash of a compressed file for `NamingScheme::ContentHash`. This is
+/// FNV-1a, which unlike `std`'s hashers is the same on every platform and
+/// Rust version.
+#[cfg(engine)]
+fn content_hash(compressed: &[u8]) -> String {
+    let hash = compressed
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    format!("{:0width$x}", hash >> 32, width = CONTENT_HASH_LEN)
+}
+
+/// `compressed` with `hash` before its extension, e.g. `main.css.1a2b3c4d.br`
+#[cfg(engine)]
+fn with_content_hash(compressed: &Path, hash: &str) -> PathBuf {
+    let extension = compressed.extension().unwrap_or_default();
+    compressed.with_extension(format!("{}.{}", hash, extension.to_string_lossy()))
+}
+
+/// `path` without the hash from `with_content_hash`, or `None` if it doesn't
+/// have one
+#[cfg(engine)]
+fn without_content_hash(path: &Path) -> Option<PathBuf> {
+    let extension = path.extension()?;
+    let hashed = path.with_extension("");
+    let hash = hashed.extension()?.to_str()?;
+    let is_hash = hash.len() == CONTENT_HASH_LEN
+        && hash
+            .bytes()
+            .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'));
+    is_hash.then(|| hashed.with_extension(extension))
+}
+
+/// The files that exist for a path from `compressed_path`. That's the path
+/// itself, or with `NamingScheme::ContentHash` the path with any hash.
+#[cfg(engine)]
+fn existing_outputs(compressed: &Path, naming: &NamingScheme) -> Vec<PathBuf> {
+    if *naming != NamingScheme::ContentHash {
+        return compressed
+            .exists()
+            .then(|| compressed.to_path_buf())
+            .into_iter()
+            .collect();
+    }
+    let dir = match compressed.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| compressed.with_file_name(entry.file_name()))
+        .filter(|path| without_content_hash(path).as_deref() == Some(compressed))
+        .collect()
+}
+
+/// Deletes the files `existing_outputs` finds for `compressed`, except `keep`
+#[cfg(engine)]
+fn remove_outputs(
+    compressed: &Path,
+    naming: &NamingScheme,
+    keep: Option<&Path>,
+) -> Result<(), CompressionError> {
+    for existing in existing_outputs(compressed, naming) {
+        if Some(existing.as_path()) == keep {
+            continue;
+        }
+        match std::fs::remove_file(&existing) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                return Err(CompressionError::io(&existing)(err));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// The tests check the outputs brotli writes
+#[cfg(all(test, engine, feature = "brotli"))]
+mod tests {
+    use super::*;
+
+    const CSS: &str = "body { color: red; margin: 0 auto; } .a > .b { padding: 4px; }\n";
+
+    /// A temporary directory and its path relative to the working directory
+    /// with a leading `./`, so globs can be written like the documented ones
+    fn temp_dir() -> (tempfile::TempDir, String) {
+        let dir = tempfile::Builder::new()
+            .prefix(".perseus-compress-test")
+            .tempdir_in(".")
+            .unwrap();
+        let root = format!("./{}", dir.path().file_name().unwrap().to_string_lossy());
+        (dir, root)
+    }
+
+    fn write(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) {
+        let path = path.as_ref();
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    /// Options that compress what `include` matches, leaving out the default
+    /// HTML, SVG and font includes
+    fn options(include: String) -> CompressionOptions<String> {
+        CompressionOptions {
+            include: vec![include],
+            include_html: false,
+            include_svg: false,
+            include_fonts: false,
+            ..CompressionOptions::converted_defaults()
+        }
+    }
+
+    #[test]
+    fn output_dir_keeps_nested_paths() {
+        let (_dir, root) = temp_dir();
+        write(format!("{root}/dist/static/css/main.css"), CSS.repeat(100));
+        write(
+            format!("{root}/dist/static/print/main.css"),
+            CSS.repeat(100),
+        );
+        let options = CompressionOptions {
+            output_dir: Some(format!("{root}/out").into()),
+            ..options(format!("{root}/dist/static/**/*.css"))
+        };
+
+        let report = compress_paths(&options).unwrap();
+        assert_eq!(report.files_compressed, 2);
+        assert!(Path::new(&format!("{root}/out/css/main.css.br")).is_file());
+        assert!(Path::new(&format!("{root}/out/print/main.css.br")).is_file());
+        assert!(Path::new(&format!("{root}/out/css/main.css")).is_file());
+    }
+
+    #[test]
+    fn overrides_apply_to_globs_with_leading_cur_dir() {
+        let (_dir, root) = temp_dir();
+        write(format!("{root}/dist/pkg/app.wasm"), CSS.repeat(100));
+        write(format!("{root}/dist/static/main.css"), CSS.repeat(100));
+        let skip_wasm = PerFileOptions {
+            min_size: Some(100_000_000),
+            ..PerFileOptions::default()
+        };
+        let options = CompressionOptions {
+            overrides: vec![(format!("{root}/dist/pkg/*.wasm"), skip_wasm)],
+            ..options(format!("{root}/dist/**/*"))
+        };
+
+        let report = compress_paths(&options).unwrap();
+        assert_eq!(report.files_compressed, 1);
+        assert_eq!(report.files_skipped_size_threshold, 1);
+        assert!(!Path::new(&format!("{root}/dist/pkg/app.wasm.br")).exists());
+        assert!(Path::new(&format!("{root}/dist/static/main.css.br")).is_file());
+
+        // A path given with `./` matches the same override
+        let wasm = format!("{root}/dist/pkg/app.wasm");
+        assert_eq!(options.file_options(Path::new(&wasm)).min_size, 100_000_000);
+    }
+
+    #[test]
+    fn overrides_change_algorithm_and_level() {
+        let (_dir, root) = temp_dir();
+        // Varied enough that brotli's quality makes a difference
+        let css = (0..2000)
+            .map(|i| format!(".c{} {{ margin: {}px {}em; }}\n", i, i % 37, i % 11))
+            .collect::<String>();
+        for dir in ["default", "fastest", "quality"] {
+            write(format!("{root}/{dir}/main.css"), &css);
+        }
+        let quality_1 = Algorithm::Brotli(BrotliParams {
+            quality: 1,
+            ..BrotliParams::default()
+        });
+        let options = CompressionOptions {
+            algorithms: vec![Algorithm::Brotli(BrotliParams::default())],
+            overrides: vec![
+                (
+                    format!("{root}/fastest/*.css"),
+                    PerFileOptions {
+                        level: Some(CompressionLevel::Fastest),
+                        ..PerFileOptions::default()
+                    },
+                ),
+                (
+                    format!("{root}/quality/*.css"),
+                    PerFileOptions {
+                        algorithms: Some(vec![quality_1]),
+                        ..PerFileOptions::default()
+                    },
+                ),
+            ],
+            ..options(format!("{root}/*/main.css"))
+        };
+
+        compress_paths(&options).unwrap();
+        let size = |dir: &str| {
+            std::fs::metadata(format!("{root}/{dir}/main.css.br"))
+                .unwrap()
+                .len()
+        };
+        assert!(size("fastest") > size("default"));
+        assert!(size("quality") > size("default"));
+    }
+
+    #[cfg(feature = "cache")]
+    #[test]
+    fn hand_written_outputs_are_kept() {
+        let (_dir, root) = temp_dir();
+        let generated = format!("{root}/dist/static/generated.css");
+        let hand_written = format!("{root}/dist/static/hand.css");
+        write(&generated, CSS.repeat(100));
+        let mut options = CompressionOptions {
+            algorithms: vec![Algorithm::Brotli(BrotliParams::default())],
+            cache: Some(format!("{root}/cache.json").into()),
+            ..options(format!("{root}/dist/static/**/*.css"))
+        };
+        // Compressed files matched by an include are still the plugin's own
+        options.include.push(format!("{root}/dist/static/**/*.br"));
+        compress_paths(&options).unwrap();
+
+        write(&generated, CSS.repeat(200));
+        write(&hand_written, CSS.repeat(100));
+        write(format!("{hand_written}.br"), b"hand");
+        let report = compress_paths(&options).unwrap();
+        assert_eq!(report.files_compressed, 1);
+        assert_eq!(
+            std::fs::read(format!("{hand_written}.br")).unwrap(),
+            b"hand"
+        );
+        let decoded = Algorithm::Brotli(BrotliParams::default())
+            .decode(&std::fs::read(format!("{generated}.br")).unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(decoded, CSS.repeat(200).as_bytes());
+
+        options.force = true;
+        compress_paths(&options).unwrap();
+        assert_ne!(
+            std::fs::read(format!("{hand_written}.br")).unwrap(),
+            b"hand"
+        );
+    }
+
+    #[cfg(feature = "cache")]
+    #[test]
+    fn corrupt_cache_compresses_everything() {
+        let (_dir, root) = temp_dir();
+        write(format!("{root}/dist/main.css"), CSS.repeat(100));
+        write(format!("{root}/cache.json"), "{ not json");
+        let options = CompressionOptions {
+            algorithms: vec![Algorithm::Brotli(BrotliParams::default())],
+            cache: Some(format!("{root}/cache.json").into()),
+            ..options(format!("{root}/dist/*.css"))
+        };
+        let report = compress_paths(&options).unwrap();
+        assert_eq!(report.files_compressed, 1);
+        assert!(Path::new(&format!("{root}/dist/main.css.br")).is_file());
+
+        // The corrupt cache was replaced with a working one
+        let report = compress_paths(&options).unwrap();
+        assert_eq!(report.files_skipped_up_to_date, 1);
+    }
+
+    #[cfg(feature = "cache")]
+    #[test]
+    fn cached_sources_are_recompressed_at_another_level() {
+        let (_dir, root) = temp_dir();
+        let file = format!("{root}/dist/main.css");
+        write(&file, CSS.repeat(100));
+        let mut options = CompressionOptions {
+            algorithms: vec![Algorithm::Brotli(BrotliParams::default())],
+            cache: Some(format!("{root}/cache.json").into()),
+            ..options(format!("{root}/dist/*.css"))
+        };
+        compress_paths(&options).unwrap();
+        let default = std::fs::read(format!("{file}.br")).unwrap();
+
+        options.level = CompressionLevel::Fastest;
+        let report = compress_paths(&options).unwrap();
+        assert_eq!(report.files_compressed, 1);
+        assert_eq!(report.files_skipped_up_to_date, 0);
+        assert_ne!(std::fs::read(format!("{file}.br")).unwrap(), default);
+    }
+
+    #[cfg(feature = "cache")]
+    #[test]
+    fn cached_sources_are_skipped_when_only_touched() {
+        let (_dir, root) = temp_dir();
+        let file = format!("{root}/dist/main.css");
+        write(&file, CSS.repeat(100));
+        let options = CompressionOptions {
+            algorithms: vec![Algorithm::Brotli(BrotliParams::default())],
+            cache: Some(format!("{root}/cache.json").into()),
+            ..options(format!("{root}/dist/*.css"))
+        };
+        compress_paths(&options).unwrap();
+
+        // Newer than its output, as after CI restores `dist` from a cache
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(60);
+        std::fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        let report = compress_paths(&options).unwrap();
+        assert_eq!(report.files_compressed, 0);
+        assert_eq!(report.files_skipped_up_to_date, 1);
+    }
+
+    #[cfg(feature = "cache")]
+    #[test]
+    fn cached_sources_are_recompressed_when_their_output_changed() {
+        let (_dir, root) = temp_dir();
+        let file = format!("{root}/dist/main.css");
+        let output = format!("{file}.br");
+        write(&file, CSS.repeat(100));
+        let options = CompressionOptions {
+            algorithms: vec![Algorithm::Brotli(BrotliParams::default())],
+            cache: Some(format!("{root}/cache.json").into()),
+            ..options(format!("{root}/dist/*.css"))
+        };
+        compress_paths(&options).unwrap();
+        let compressed = std::fs::read(&output).unwrap();
+
+        std::fs::remove_file(&output).unwrap();
+        let report = compress_paths(&options).unwrap();
+        assert_eq!(report.files_compressed, 1);
+        assert_eq!(std::fs::read(&output).unwrap(), compressed);
+
+        write(&output, b"edited");
+        let report = compress_paths(&options).unwrap();
+        assert_eq!(report.files_compressed, 1);
+        assert_eq!(std::fs::read(&output).unwrap(), compressed);
+    }
+
+    #[cfg(all(feature = "cache", feature = "gzip"))]
+    #[test]
+    fn clean_keeps_hand_written_outputs() {
+        let (_dir, root) = temp_dir();
+        let generated = format!("{root}/dist/static/generated.css");
+        let hand_written = format!("{root}/dist/static/hand.css");
+        write(&generated, CSS.repeat(100));
+        let mut options = CompressionOptions {
+            algorithms: vec![
+                Algorithm::Brotli(BrotliParams::default()),
+                Algorithm::Gzip(GzipParams::default()),
+            ],
+            cache: Some(format!("{root}/cache.json").into()),
+            ..options(format!("{root}/dist/static/**/*.css"))
+        };
+        compress_paths(&options).unwrap();
+        // The `.gz` file is left over from before gzip was switched off
+        options.algorithms.truncate(1);
+        compress_paths(&options).unwrap();
+        write(&hand_written, CSS.repeat(100));
+        write(format!("{hand_written}.br"), b"hand");
+
+        assert_eq!(clean_compressed_files(&options).unwrap(), 2);
+        assert!(!Path::new(&format!("{generated}.br")).exists());
+        assert!(!Path::new(&format!("{generated}.gz")).exists());
+        assert!(Path::new(&format!("{hand_written}.br")).exists());
+    }
+
+    #[test]
+    fn in_place_recompresses_with_force() {
+        let (_dir, root) = temp_dir();
+        let file = format!("{root}/dist/main.css");
+        write(&file, CSS.repeat(100));
+        let brotli = Algorithm::Brotli(BrotliParams::default());
+        let mut options = CompressionOptions {
+            algorithms: vec![brotli],
+            in_place: true,
+            // The compressed file is smaller than the default minimum
+            min_size: 0,
+            ..options(file.clone())
+        };
+        compress_paths(&options).unwrap();
+        let compressed = std::fs::read(&file).unwrap();
+
+        let report = compress_paths(&options).unwrap();
+        assert_eq!(report.files_skipped_up_to_date, 1);
+        assert_eq!(std::fs::read(&file).unwrap(), compressed);
+
+        // Compressing it again only makes it larger, so it's discarded rather
+        // than skipped
+        options.force = true;
+        let report = compress_paths(&options).unwrap();
+        assert_eq!(report.files_skipped_up_to_date, 0);
+        assert_eq!(report.files_skipped_poor_ratio, 1);
+        assert_eq!(std::fs::read(&file).unwrap(), compressed);
+    }
+
+    #[cfg(all(feature = "cache", feature = "json"))]
+    #[test]
+    fn own_files_are_not_compressed() {
+        let (_dir, root) = temp_dir();
+        write(format!("{root}/dist/state.json"), CSS.repeat(100));
+        let options = CompressionOptions::<String> {
+            include: vec![],
+            base_dir: Some(root.clone().into()),
+            algorithms: vec![Algorithm::Brotli(BrotliParams::default())],
+            cache: Some(format!("{root}/dist/.perseus-compress-cache.json").into()),
+            manifest: Some(format!("{root}/dist/manifest.json").into()),
+            min_size: 0,
+            ..CompressionOptions::converted_defaults()
+        };
+        compress_paths(&options).unwrap();
+        // The cache and manifest exist from now on
+        write(format!("{root}/dist/state.json"), CSS.repeat(200));
+        let report = compress_paths(&options).unwrap();
+
+        assert_eq!(report.files_compressed, 1);
+        assert!(Path::new(&format!("{root}/dist/state.json.br")).is_file());
+        assert!(!Path::new(&format!("{root}/dist/.perseus-compress-cache.json.br")).exists());
+        assert!(!Path::new(&format!("{root}/dist/manifest.json.br")).exists());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn report_is_written_to_base_dir() {
+        let (_dir, root) = temp_dir();
+        write(format!("{root}/dist/state.json"), CSS.repeat(100));
+        let options = CompressionOptions {
+            base_dir: Some(root.clone().into()),
+            algorithms: vec![Algorithm::Brotli(BrotliParams::default())],
+            write_report: true,
+            min_size: 0,
+            ..options("dist/*.json".to_string())
+        };
+        run_plugin::<String>(&options).unwrap();
+        assert!(Path::new(&format!("{root}/dist/compress-report.json")).is_file());
+
+        // The report from the first run is recognised as the plugin's own
+        write(format!("{root}/dist/state.json"), CSS.repeat(200));
+        run_plugin::<String>(&options).unwrap();
+        assert!(Path::new(&format!("{root}/dist/state.json.br")).is_file());
+        assert!(!Path::new(&format!("{root}/dist/compress-report.json.br")).exists());
+    }
+
+    #[test]
+    fn incremental_skips_up_to_date_files() {
+        let (_dir, root) = temp_dir();
+        write(format!("{root}/dist/main.css"), CSS.repeat(100));
+        let mut options = CompressionOptions {
+            algorithms: vec![Algorithm::Brotli(BrotliParams::default())],
+            ..options(format!("{root}/dist/*.css"))
+        };
+        assert_eq!(compress_paths(&options).unwrap().files_compressed, 1);
+
+        let report = compress_paths(&options).unwrap();
+        assert_eq!(report.files_compressed, 0);
+        assert_eq!(report.files_skipped_up_to_date, 1);
+
+        options.incremental = false;
+        let report = compress_paths(&options).unwrap();
+        assert_eq!(report.files_compressed, 1);
+        assert_eq!(report.files_skipped_up_to_date, 0);
+    }
+
+    #[test]
+    fn compress_file_matches_compress_paths() {
+        let (_dir, root) = temp_dir();
+        let file = format!("{root}/dist/main.css");
+        write(&file, CSS.repeat(100));
+        let brotli = Algorithm::Brotli(BrotliParams::default());
+        let options = CompressionOptions {
+            algorithms: vec![brotli],
+            ..options(file.clone())
+        };
+        compress_paths(&options).unwrap();
+
+        let output = format!("{root}/main.css.br");
+        let result = brotli
+            .compress_file(
+                Path::new(&file),
+                Path::new(&output),
+                CompressionLevel::Default,
+            )
+            .unwrap();
+        let compressed = std::fs::read(&output).unwrap();
+        assert_eq!(compressed, std::fs::read(format!("{file}.br")).unwrap());
+        assert_eq!(result.compressed_size, compressed.len() as u64);
+    }
+
+    #[test]
+    fn custom_compressor_replaces_first_algorithm() {
+        /// Brotli at the lowest quality, so its output differs from the
+        /// built-in encoder's
+        struct FastBrotli;
+
+        impl Compressor for FastBrotli {
+            fn compress(&self, input: &Path, output: &Path) -> Result<(), CompressionError> {
+                let fast = Algorithm::Brotli(BrotliParams {
+                    quality: 0,
+                    ..BrotliParams::default()
+                });
+                fast.compress_file(input, output, CompressionLevel::Default)
+                    .map(|_| ())
+            }
+        }
+
+        let (_dir, root) = temp_dir();
+        let file = format!("{root}/dist/main.css");
+        write(&file, CSS.repeat(100));
+        let brotli = Algorithm::Brotli(BrotliParams::default());
+        let options = CompressionOptions {
+            algorithms: vec![brotli],
+            custom_compressor: Some(Box::new(FastBrotli)),
+            ..options(file.clone())
+        };
+
+        assert_eq!(compress_paths(&options).unwrap().files_compressed, 1);
+        let compressed = std::fs::read(format!("{file}.br")).unwrap();
+        let built_in = brotli.compress(CSS.repeat(100).as_bytes(), CompressionLevel::Default);
+        assert_ne!(compressed, built_in.unwrap());
+        let decoded = brotli.decode(&compressed).unwrap().unwrap();
+        assert_eq!(decoded, CSS.repeat(100).as_bytes());
+    }
+
+    #[test]
+    fn profiles_resolve_to_pinned_parameters() {
+        let default_brotli = Algorithm::Brotli(BrotliParams {
+            quality: 11,
+            lgwin: 22,
+            mode: BrotliMode::Auto,
+            large_window_threshold: None,
+            multithread_threshold: None,
+        });
+        for profile in [CompressionProfile::Fastest, CompressionProfile::Balanced] {
+            assert_eq!(profile.algorithms()[0], default_brotli);
+            assert_eq!(profile.algorithms(), Algorithm::enabled());
+        }
+        let smallest = vec![
+            Algorithm::Brotli(BrotliParams {
+                quality: 11,
+                lgwin: 24,
+                mode: BrotliMode::Auto,
+                large_window_threshold: None,
+                multithread_threshold: None,
+            }),
+            #[cfg(feature = "gzip")]
+            Algorithm::Gzip(GzipParams {
+                level: 9,
+                #[cfg(feature = "zopfli")]
+                backend: GzipBackend::Zopfli,
+                #[cfg(not(feature = "zopfli"))]
+                backend: GzipBackend::Flate2,
+            }),
+            #[cfg(feature = "deflate")]
+            Algorithm::Deflate(DeflateParams { level: 9 }),
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd(ZstdParams { level: 22 }),
+            #[cfg(feature = "xz")]
+            Algorithm::Xz(XzParams { preset: 9 }),
+        ];
+        assert_eq!(CompressionProfile::Smallest.algorithms(), smallest);
+        assert_eq!(
+            CompressionProfile::Fastest.level(),
+            CompressionLevel::Fastest
+        );
+        assert_eq!(
+            CompressionProfile::Balanced.level(),
+            CompressionLevel::Default
+        );
+        assert_eq!(
+            CompressionProfile::Smallest.level(),
+            CompressionLevel::Default
+        );
+
+        // Explicit fields win over the profile
+        let options = CompressionOptions {
+            level: CompressionLevel::Best,
+            ..CompressionOptions::with_profile(CompressionProfile::Smallest)
+        };
+        assert_eq!(options.algorithms, smallest);
+        assert_eq!(options.level, CompressionLevel::Best);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_run_writes_every_output() {
+        let (_dir, root) = temp_dir();
+        for i in 0..64 {
+            write(format!("{root}/dist/{i}.css"), CSS.repeat(20 + i));
+        }
+        let options = CompressionOptions {
+            algorithms: vec![Algorithm::Brotli(BrotliParams::default())],
+            threads: Threads::Fixed(4),
+            ..options(format!("{root}/dist/*.css"))
+        };
+
+        let report = compress_paths(&options).unwrap();
+        assert_eq!(report.threads, 4);
+        assert_eq!(report.files_compressed, 64);
+        assert!(report.failures.is_empty());
+        for i in 0..64 {
+            let compressed = std::fs::read(format!("{root}/dist/{i}.css.br")).unwrap();
+            let decoded = Algorithm::Brotli(BrotliParams::default()).decode(&compressed);
+            assert_eq!(decoded.unwrap().unwrap(), CSS.repeat(20 + i).as_bytes());
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn files_larger_than_the_budget_are_compressed() {
+        let (_dir, root) = temp_dir();
+        write(format!("{root}/dist/small.css"), CSS.repeat(20));
+        write(format!("{root}/dist/large.css"), CSS.repeat(200));
+        let mut options = CompressionOptions {
+            algorithms: vec![Algorithm::Brotli(BrotliParams::default())],
+            threads: Threads::Fixed(2),
+            max_in_flight_bytes: Some(CSS.len() as u64 * 100),
+            ..options(format!("{root}/dist/*.css"))
+        };
+        let report = compress_paths(&options).unwrap();
+        assert_eq!(report.files_compressed, 2);
+        assert!(Path::new(&format!("{root}/dist/large.css.br")).is_file());
+
+        // A budget of 0 would never hold anything back
+        options.max_in_flight_bytes = Some(0);
+        assert!(matches!(
+            compress_paths(&options),
+            Err(CompressionError::OutOfRange { value: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn svgs_are_compressed_by_default() {
+        const ICONS: &str = r#"<svg xmlns="http://www.w3.org/2000/svg" style="display: none">
+  <symbol id="home" viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round">
+    <path d="M3 9l9-7 9 7v11a2 2 0 0 1-2 2H5a2 2 0 0 1-2-2z"/>
+    <polyline points="9 22 9 12 15 12 15 22"/>
+  </symbol>
+  <symbol id="search" viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round">
+    <circle cx="11" cy="11" r="8"/>
+    <line x1="21" y1="21" x2="16.65" y2="16.65"/>
+  </symbol>
+  <symbol id="settings" viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round">
+    <circle cx="12" cy="12" r="3"/>
+    <path d="M19.4 15a1.65 1.65 0 0 0 .33 1.82l.06.06a2 2 0 0 1-2.83 2.83l-.06-.06a1.65 1.65 0 0 0-1.82-.33 1.65 1.65 0 0 0-1 1.51V21a2 2 0 0 1-4 0v-.09A1.65 1.65 0 0 0 9 19.4a1.65 1.65 0 0 0-1.82.33l-.06.06a2 2 0 0 1-2.83-2.83l.06-.06a1.65 1.65 0 0 0 .33-1.82 1.65 1.65 0 0 0-1.51-1H3a2 2 0 0 1 0-4h.09A1.65 1.65 0 0 0 4.6 9a1.65 1.65 0 0 0-.33-1.82l-.06-.06a2 2 0 0 1 2.83-2.83l.06.06a1.65 1.65 0 0 0 1.82.33H9a1.65 1.65 0 0 0 1-1.51V3a2 2 0 0 1 4 0v.09a1.65 1.65 0 0 0 1 1.51 1.65 1.65 0 0 0 1.82-.33l.06-.06a2 2 0 0 1 2.83 2.83l-.06.06a1.65 1.65 0 0 0-.33 1.82V9a1.65 1.65 0 0 0 1.51 1H21a2 2 0 0 1 0 4h-.09a1.65 1.65 0 0 0-1.51 1z"/>
+  </symbol>
+  <symbol id="user" viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round">
+    <path d="M20 21v-2a4 4 0 0 0-4-4H8a4 4 0 0 0-4 4v2"/>
+    <circle cx="12" cy="7" r="4"/>
+  </symbol>
+  <symbol id="menu" viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round">
+    <line x1="3" y1="12" x2="21" y2="12"/>
+    <line x1="3" y1="6" x2="21" y2="6"/>
+    <line x1="3" y1="18" x2="21" y2="18"/>
+  </symbol>
+</svg>
+"#;
+
+        let (_dir, root) = temp_dir();
+        let icons = format!("{root}/dist/static/icons.svg");
+        write(&icons, ICONS);
+        let options = CompressionOptions::<String> {
+            include: vec![],
+            base_dir: Some(root.into()),
+            algorithms: vec![Algorithm::Brotli(BrotliParams::default())],
+            ..CompressionOptions::converted_defaults()
+        };
+
+        let report = compress_paths(&options).unwrap();
+        assert_eq!(report.files_compressed, 1);
+        let compressed = std::fs::read(format!("{icons}.br")).unwrap();
+        assert!(compressed.len() < ICONS.len());
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mapped_sources_compress_like_read_ones() {
+        let (_dir, root) = temp_dir();
+        let file = format!("{root}/dist/main.css");
+        let empty = format!("{root}/dist/empty.css");
+        write(&file, CSS.repeat(100));
+        write(&empty, "");
+        let options = CompressionOptions {
+            algorithms: vec![Algorithm::Brotli(BrotliParams::default())],
+            mmap_threshold: Some(0),
+            ..options(file.clone())
+        };
+        let size = CSS.len() as u64 * 100;
+        let source = read_source(Path::new(&file), size, &options).unwrap();
+        assert!(matches!(source, Source::Mapped(_)));
+        let source = read_source(Path::new(&empty), 0, &options).unwrap();
+        assert!(matches!(source, Source::Read(_)));
+        compress_paths(&options).unwrap();
+
+        let read = CompressionOptions {
+            mmap_threshold: None,
+            ..options
+        };
+        let output = format!("{root}/main.css.br");
+        compress_file(Path::new(&file), Path::new(&output), &read).unwrap();
+        assert_eq!(
+            std::fs::read(format!("{file}.br")).unwrap(),
+            std::fs::read(&output).unwrap()
+        );
+    }
+
+    #[test]
+    fn leftover_outputs_of_incompressible_files_are_removed() {
+        let (_dir, root) = temp_dir();
+        let file = format!("{root}/dist/noise.bin");
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let noise = (0..4096)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                (state >> 56) as u8
+            })
+            .collect::<Vec<_>>();
+        write(&file, &noise);
+        let options = CompressionOptions {
+            algorithms: vec![Algorithm::Brotli(BrotliParams::default())],
+            min_size: 0,
+            ..options(file.clone())
+        };
+        let report = compress_paths(&options).unwrap();
+        assert_eq!(report.outputs_discarded, 1);
+
+        // From an earlier build, when the file was still compressible
+        let leftover = format!("{file}.br");
+        write(&leftover, b"stale");
+        let earlier = std::time::SystemTime::now() - std::time::Duration::from_secs(60);
+        std::fs::File::options()
+            .write(true)
+            .open(&leftover)
+            .unwrap()
+            .set_modified(earlier)
+            .unwrap();
+        let report = compress_paths(&options).unwrap();
+        assert_eq!(report.outputs_discarded, 1);
+        assert!(!Path::new(&leftover).exists());
+    }
 }
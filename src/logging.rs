@@ -0,0 +1,36 @@
+//! Logging through the `log` crate, filtered by `CompressionOptions::log_level`.
+//! Without the `log` feature these compile to nothing.
+
+/// Logs at `level` if the options' `log_level` allows it
+macro_rules! log_at {
+    ($options:expr, $level:ident, $($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        if log::Level::$level <= $options.log_level {
+            log::log!(log::Level::$level, $($arg)+);
+        }
+        // Keep the arguments used so they don't warn without the feature
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = &$options;
+            let _ = format!($($arg)+);
+        }
+    }};
+}
+
+macro_rules! debug {
+    ($options:expr, $($arg:tt)+) => {
+        log_at!($options, Debug, $($arg)+)
+    };
+}
+
+macro_rules! info {
+    ($options:expr, $($arg:tt)+) => {
+        log_at!($options, Info, $($arg)+)
+    };
+}
+
+macro_rules! warn {
+    ($options:expr, $($arg:tt)+) => {
+        log_at!($options, Warn, $($arg)+)
+    };
+}
@@ -0,0 +1,108 @@
+//! The JSON manifest written to `CompressionOptions::manifest`, and the
+//! lookup of a source's compressed files it shares with the headers file
+
+use crate::encoder::LoadedEncoder;
+use crate::{existing_outputs, CompressionOptions, Encoder, FileOptions};
+#[cfg(feature = "json")]
+use crate::{replace_file, CompressionError, CompressionFileResult};
+#[cfg(feature = "json")]
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+/// Version of the manifest's schema, bumped on incompatible changes
+#[cfg(feature = "json")]
+const MANIFEST_VERSION: u32 = 1;
+
+/// A compressed file of a source that exists on disk
+pub(crate) struct Variant<'a> {
+    pub(crate) encoder: &'a dyn Encoder,
+    pub(crate) path: PathBuf,
+    /// Only the manifest lists sizes
+    #[cfg(feature = "json")]
+    pub(crate) size: u64,
+}
+
+/// The compressed files of `file` that exist next to `output`, the path its
+/// outputs are named after
+pub(crate) fn variants<'a, M: AsRef<str> + Send>(
+    file: &Path,
+    output: &Path,
+    encoders: &'a [LoadedEncoder],
+    options: &CompressionOptions<M>,
+) -> Vec<Variant<'a>> {
+    let FileOptions { algorithms, .. } = options.file_options(file);
+    encoders
+        .iter()
+        .filter(|loaded| loaded.used_for(algorithms))
+        .filter_map(|loaded| {
+            let compressed = options.compressed_path(output, &*loaded.encoder);
+            // The output's name, which includes its hash with
+            // `NamingScheme::ContentHash`
+            let path = existing_outputs(&compressed, &options.naming)
+                .into_iter()
+                .next()?;
+            #[cfg(feature = "json")]
+            let size = std::fs::metadata(&path).ok()?.len();
+            Some(Variant {
+                encoder: &*loaded.encoder,
+                path,
+                #[cfg(feature = "json")]
+                size,
+            })
+        })
+        .collect()
+}
+
+/// Writes a manifest of the compressed files of every source in `files` to
+/// `path`. Outputs are looked up on disk, so files that were up to date are
+/// listed too.
+#[cfg(feature = "json")]
+pub(crate) fn write_manifest<'a, M: AsRef<str> + Send>(
+    path: &Path,
+    files: impl Iterator<Item = &'a Path>,
+    encoders: &[LoadedEncoder],
+    options: &CompressionOptions<M>,
+    output_path: impl Fn(&Path) -> PathBuf,
+) -> Result<(), CompressionError> {
+    let mut manifest = serde_json::Map::new();
+    for file in files {
+        let original_size = std::fs::metadata(file)
+            .map_err(CompressionError::io(file))?
+            .len();
+        let variants = variants(file, &output_path(file), encoders, options)
+            .into_iter()
+            .map(|variant| {
+                let sizes = CompressionFileResult {
+                    original_size,
+                    compressed_size: variant.size,
+                };
+                json!({
+                    "encoding": variant.encoder.suffix(),
+                    "path": variant.path,
+                    "size": variant.size,
+                    "ratio": sizes.ratio(),
+                    "dictionary": variant.encoder.requires_dictionary(),
+                })
+            })
+            .collect::<Vec<_>>();
+        if variants.is_empty() {
+            continue;
+        }
+        let entry = json!({
+            "size": original_size,
+            "variants": variants,
+        });
+        manifest.insert(file.to_string_lossy().into_owned(), entry);
+    }
+
+    let manifest = json!({
+        "version": MANIFEST_VERSION,
+        "files": manifest,
+    });
+    let json = serde_json::to_vec_pretty(&manifest)
+        .map_err(std::io::Error::from)
+        .map_err(CompressionError::io(path))?;
+    // Deploy steps read the manifest, so never leave a partially written one
+    // behind, even without the `tempfile` feature
+    replace_file(path, &json)
+}
@@ -0,0 +1,271 @@
+use crate::CompressionError;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Summary of a compression run, as returned by `compress_paths`.
+#[derive(Debug, Default)]
+pub struct CompressionReport {
+    /// Number of source files that were compressed
+    pub files_compressed: usize,
+    /// Number of source files that were skipped, e.g. because they're below
+    /// the minimum size, already up to date or didn't get any smaller
+    pub files_skipped: usize,
+    /// Number of the skipped files whose compressed files were already newer
+    /// than the source, see `CompressionOptions::force`
+    pub files_skipped_up_to_date: usize,
+    /// Number of the skipped files that were skipped for being smaller than
+    /// `CompressionOptions::min_size` or larger than
+    /// `CompressionOptions::max_size`
+    pub files_skipped_size_threshold: usize,
+    /// Number of the skipped files where no compressed file was written
+    /// because none saved enough space, see
+    /// `CompressionOptions::skip_if_larger` and `CompressionOptions::min_ratio`
+    pub files_skipped_poor_ratio: usize,
+    /// Number of compressed files that weren't written because they didn't
+    /// save enough space, including those of sources that still got other
+    /// compressed files, e.g. a `.gz` discarded next to a `.br` that was kept
+    pub outputs_discarded: usize,
+    /// Number of compressed files deleted because their source no longer
+    /// exists, see `CompressionOptions::prune_orphans`
+    pub files_pruned: usize,
+    /// Total size of the compressed sources in bytes. Sources compressed with
+    /// multiple algorithms are counted once per algorithm.
+    pub bytes_before: u64,
+    /// Total size of the compressed files in bytes
+    pub bytes_after: u64,
+    /// How long the run took
+    pub duration: Duration,
+    /// How many threads files were compressed on, see
+    /// `CompressionOptions::threads`
+    pub threads: usize,
+    /// One entry for every compressed file that was written
+    pub files: Vec<FileReport>,
+    /// Files that failed to compress. Only populated with
+    /// `ErrorPolicy::SkipAndWarn`, otherwise failures abort the run.
+    pub failures: Vec<(PathBuf, CompressionError)>,
+}
+
+impl CompressionReport {
+    /// Compressed size divided by original size. Lower is better.
+    pub fn ratio(&self) -> f64 {
+        ratio(self.bytes_before, self.bytes_after)
+    }
+
+    /// How many bytes compression saved in total
+    pub fn bytes_saved(&self) -> u64 {
+        self.bytes_before.saturating_sub(self.bytes_after)
+    }
+
+    /// Records what happened to a single source file
+    #[cfg(engine)]
+    pub(crate) fn add_source(&mut self, outcome: SourceOutcome) {
+        let files = match outcome {
+            SourceOutcome::Compressed { files, discarded } => {
+                self.outputs_discarded += discarded;
+                files
+            }
+            SourceOutcome::UpToDate => {
+                self.files_skipped += 1;
+                self.files_skipped_up_to_date += 1;
+                return;
+            }
+            SourceOutcome::SizeThreshold => {
+                self.files_skipped += 1;
+                self.files_skipped_size_threshold += 1;
+                return;
+            }
+            SourceOutcome::PoorRatio { discarded } => {
+                self.outputs_discarded += discarded;
+                self.files_skipped += 1;
+                self.files_skipped_poor_ratio += 1;
+                return;
+            }
+        };
+        self.files_compressed += 1;
+        for file in &files {
+            self.bytes_before += file.original_size;
+            self.bytes_after += file.compressed_size;
+        }
+        self.files.extend(files);
+    }
+
+    /// A table of every compressed file with its sizes, one row per file,
+    /// e.g. for printing the results of a dry run
+    pub fn table(&self) -> String {
+        let rows = self
+            .files
+            .iter()
+            .map(|file| {
+                [
+                    file.source.display().to_string(),
+                    file.encoding.clone(),
+                    file.original_size.to_string(),
+                    file.compressed_size.to_string(),
+                    format!("{:.1}%", file.ratio() * 100.0),
+                ]
+            })
+            .collect::<Vec<_>>();
+        let header = ["source", "encoding", "original", "compressed", "ratio"].map(String::from);
+        let mut widths = header.each_ref().map(String::len);
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.len());
+            }
+        }
+
+        let mut table = String::new();
+        for row in std::iter::once(&header).chain(&rows) {
+            // Left align the path and encoding, right align the numbers
+            let line = format!(
+                "{:<w0$}  {:<w1$}  {:>w2$}  {:>w3$}  {:>w4$}",
+                row[0],
+                row[1],
+                row[2],
+                row[3],
+                row[4],
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2],
+                w3 = widths[3],
+                w4 = widths[4],
+            );
+            table.push_str(line.trim_end());
+            table.push('\n');
+        }
+        table.push_str(&self.to_string());
+        table
+    }
+
+    #[cfg(all(engine, feature = "json"))]
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        use serde_json::json;
+
+        json!({
+            "files_compressed": self.files_compressed,
+            "files_skipped": self.files_skipped,
+            "files_skipped_up_to_date": self.files_skipped_up_to_date,
+            "files_skipped_size_threshold": self.files_skipped_size_threshold,
+            "files_skipped_poor_ratio": self.files_skipped_poor_ratio,
+            "outputs_discarded": self.outputs_discarded,
+            "files_pruned": self.files_pruned,
+            "bytes_before": self.bytes_before,
+            "bytes_after": self.bytes_after,
+            "duration_ms": self.duration.as_millis() as u64,
+            "threads": self.threads,
+            "files": self.files.iter().map(|file| json!({
+                "source": file.source,
+                "output": file.output,
+                "encoding": file.encoding,
+                "original_size": file.original_size,
+                "compressed_size": file.compressed_size,
+                "dictionary": file.dictionary,
+                "downgraded": file.downgraded,
+            })).collect::<Vec<_>>(),
+            "failures": self.failures.iter().map(|(path, err)| json!({
+                "path": path,
+                "error": err.to_string(),
+            })).collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl std::fmt::Display for CompressionReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "compressed {} file(s), skipped {} ({} up to date), {} bytes -> {} bytes ({:.1}%) in {:.2?} on {} thread(s)",
+            self.files_compressed,
+            self.files_skipped,
+            self.files_skipped_up_to_date,
+            self.bytes_before,
+            self.bytes_after,
+            self.ratio() * 100.0,
+            self.duration,
+            self.threads
+        )?;
+        if self.outputs_discarded > 0 {
+            write!(
+                f,
+                ", discarded {} output(s) that didn't save enough space",
+                self.outputs_discarded
+            )?;
+        }
+        if self.files_pruned > 0 {
+            write!(f, ", pruned {} orphaned file(s)", self.files_pruned)?;
+        }
+        if !self.failures.is_empty() {
+            write!(f, ", {} file(s) failed", self.failures.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// What happened to a single source file
+#[cfg(engine)]
+pub(crate) enum SourceOutcome {
+    /// The source was compressed into `files`, and `discarded` more outputs
+    /// didn't save enough space
+    Compressed {
+        files: Vec<FileReport>,
+        discarded: usize,
+    },
+    /// Every output was already up to date
+    UpToDate,
+    /// The source is outside the configured size range
+    SizeThreshold,
+    /// None of the `discarded` outputs saved enough space to be kept
+    PoorRatio { discarded: usize },
+}
+
+/// A single compressed file
+#[derive(Debug, Clone)]
+pub struct FileReport {
+    /// The original file
+    pub source: PathBuf,
+    /// The compressed file that was written
+    pub output: PathBuf,
+    /// The suffix of the encoder used to compress it, e.g. `br`
+    pub encoding: String,
+    /// Size of the original file in bytes
+    pub original_size: u64,
+    /// Size of the compressed file in bytes
+    pub compressed_size: u64,
+    /// Whether decoding needs the dictionary the file was compressed with,
+    /// e.g. because `CompressionOptions::brotli_dictionary` was set
+    pub dictionary: bool,
+    /// Whether the file was compressed at `CompressionOptions::large_file_level`
+    /// because it's larger than `CompressionOptions::large_file_threshold`
+    pub downgraded: bool,
+}
+
+impl FileReport {
+    /// Compressed size divided by original size. Lower is better.
+    pub fn ratio(&self) -> f64 {
+        ratio(self.original_size, self.compressed_size)
+    }
+}
+
+/// Sizes of a single compressed file, from `compress_file` or passed to
+/// `CompressionOptions::after_compress`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionFileResult {
+    /// Size of the original file in bytes
+    pub original_size: u64,
+    /// Size of the compressed file in bytes
+    pub compressed_size: u64,
+}
+
+impl CompressionFileResult {
+    /// Compressed size divided by original size. Lower is better.
+    pub fn ratio(&self) -> f64 {
+        ratio(self.original_size, self.compressed_size)
+    }
+}
+
+fn ratio(original: u64, compressed: u64) -> f64 {
+    if original == 0 {
+        1.0
+    } else {
+        compressed as f64 / original as f64
+    }
+}